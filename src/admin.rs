@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::{
+    body::{self, Body},
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use crate::config::LogLevel;
+use crate::encoding::ContentEncoding;
+use crate::logging::LogControl;
+use crate::proxy::Proxy;
+use crate::session_manager::{RequestRecord, SessionInfo};
+use crate::types::Error;
+use tracing::info;
+
+#[derive(Clone)]
+struct AdminState {
+    proxy: Arc<Proxy>,
+    logging: Arc<LogControl>,
+    shutdown: watch::Sender<bool>,
+}
+
+#[derive(Serialize)]
+struct CertInfo {
+    domain: String,
+    #[serde(with = "time::serde::rfc3339")]
+    expires: OffsetDateTime,
+}
+
+async fn list_sessions(State(state): State<AdminState>) -> Json<Vec<SessionInfo>> {
+    Json(state.proxy.session_manager().session_infos())
+}
+
+async fn evict_session(State(state): State<AdminState>, Path(host): Path<String>) -> StatusCode {
+    if state.proxy.session_manager().remove_session(&host) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn session_history(State(state): State<AdminState>, Path(host): Path<String>) -> Json<Vec<RequestRecord>> {
+    Json(state.proxy.session_manager().get_session_history(&host))
+}
+
+async fn list_certs(State(state): State<AdminState>) -> Json<Vec<CertInfo>> {
+    let certs = state.proxy.cert_manager().cached_certs()
+        .into_iter()
+        .map(|(domain, expires)| CertInfo { domain, expires })
+        .collect();
+    Json(certs)
+}
+
+async fn evict_cert(State(state): State<AdminState>, Path(domain): Path<String>) -> StatusCode {
+    if state.proxy.cert_manager().evict_cert(&domain) {
+        info!(component = "ADMIN", "Evicted certificate for {}", domain);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// Body maps components to levels, e.g. {"cert": "debug", "hyper": "warn"}, other components keep theirs
+async fn set_log_level(
+    State(state): State<AdminState>,
+    Json(filters): Json<HashMap<String, LogLevel>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state.logging.set_filters(&filters)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    info!(component = "ADMIN", "Log levels changed: {:?}", filters);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn shutdown(State(state): State<AdminState>) -> StatusCode {
+    info!(component = "ADMIN", "Shutdown requested through the admin API");
+    let _ = state.shutdown.send(true);
+    StatusCode::ACCEPTED
+}
+
+// True unless the client left gzip out of Accept-Encoding or gave it q=0
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT_ENCODING).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+// Gzip responses of at least `threshold` bytes for clients that accept it. Event streams are left
+// alone since compressing them would hold events back until the buffer fills
+async fn gzip(State(threshold): State<usize>, req: Request, next: Next) -> Response {
+    let accepts = accepts_gzip(req.headers());
+    let response = next.run(req).await;
+    let streaming = response.headers().get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if !accepts || streaming || response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    if bytes.len() < threshold {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    match ContentEncoding::Gzip.encode(&bytes) {
+        Ok(compressed) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            parts.headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+// Serve the admin API, it shares the proxy with the main listener so changes apply to live state
+pub async fn serve(
+    addr: SocketAddr,
+    proxy: Arc<Proxy>,
+    logging: Arc<LogControl>,
+    shutdown_tx: watch::Sender<bool>,
+    compress_threshold: usize,
+) -> Result<(), Error> {
+    let state = AdminState { proxy, logging, shutdown: shutdown_tx };
+    let app = Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/{host}", delete(evict_session))
+        .route("/sessions/{host}/history", get(session_history))
+        .route("/certs", get(list_certs))
+        .route("/certs/{domain}", delete(evict_cert))
+        .route("/log-level", post(set_log_level))
+        .route("/shutdown", post(shutdown))
+        .layer(middleware::from_fn_with_state(compress_threshold, gzip))
+        .with_state(state);
+
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("Failed to bind admin listener {}: {}", addr, e))?;
+    info!(component = "ADMIN", "Serving admin API on http://{}", addr);
+
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: usize = 1024;
+
+    // Admin-style server whose routes return a body of the given size, behind the gzip layer
+    async fn start_server() -> SocketAddr {
+        let body = |len: usize| "x".repeat(len);
+        let app = Router::new()
+            .route("/small", get(move || async move { body(THRESHOLD - 1) }))
+            .route("/large", get(move || async move { body(THRESHOLD) }))
+            .route("/events", get(move || async move {
+                ([(header::CONTENT_TYPE, "text/event-stream")], body(THRESHOLD * 4))
+            }))
+            .layer(middleware::from_fn_with_state(THRESHOLD, gzip));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    async fn get_with(addr: SocketAddr, path: &str, accept_encoding: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("http://{}{}", addr, path))
+            .header("accept-encoding", accept_encoding)
+            .send().await.unwrap()
+    }
+
+    #[test]
+    fn accept_encoding_parsing() {
+        let headers = |value: &'static str| HeaderMap::from_iter([(header::ACCEPT_ENCODING, HeaderValue::from_static(value))]);
+        assert!(accepts_gzip(&headers("gzip")));
+        assert!(accepts_gzip(&headers("br, GZIP;q=0.5")));
+        assert!(accepts_gzip(&headers("*")));
+        assert!(!accepts_gzip(&headers("gzip;q=0, br")));
+        assert!(!accepts_gzip(&headers("deflate")));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_gzipped() {
+        let addr = start_server().await;
+        let response = get_with(addr, "/large", "gzip, br").await;
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        assert_eq!(response.headers()["vary"], "accept-encoding");
+
+        let body = response.bytes().await.unwrap();
+        assert_eq!(ContentEncoding::Gzip.decode(&body).unwrap(), "x".repeat(THRESHOLD).as_bytes());
+    }
+
+    #[tokio::test]
+    async fn small_or_unwanted_responses_are_left_alone() {
+        let addr = start_server().await;
+        for (path, accept_encoding) in [("/small", "gzip"), ("/large", "br"), ("/events", "gzip")] {
+            let response = get_with(addr, path, accept_encoding).await;
+            assert!(response.headers().get("content-encoding").is_none(), "{} with {}", path, accept_encoding);
+            assert!(response.bytes().await.unwrap().iter().all(|&b| b == b'x'));
+        }
+    }
+}
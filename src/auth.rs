@@ -0,0 +1,65 @@
+use std::net::IpAddr;
+use base64::Engine;
+use hyper::{HeaderMap, Response, StatusCode};
+use ipnetwork::IpNetwork;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use crate::types::{ResponseResult, full};
+
+// Credentials clients must send in Proxy-Authorization before the proxy forwards anything
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: SecretString,
+}
+
+impl ProxyAuth {
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(encoded) = headers.get(hyper::header::PROXY_AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+
+        let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded.trim()) else {
+            return false;
+        };
+        let Ok(decoded) = String::from_utf8(decoded) else {
+            return false;
+        };
+
+        match decoded.split_once(':') {
+            Some((username, password)) => {
+                username == self.username && password == self.password.expose_secret()
+            }
+            None => false,
+        }
+    }
+}
+
+pub fn proxy_auth_required() -> ResponseResult {
+    Ok(Response::builder()
+        .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+        .header(hyper::header::PROXY_AUTHENTICATE, "Basic realm=\"boring-proxy\"")
+        .body(full("Proxy authentication required"))?)
+}
+
+// Source networks allowed to connect, an empty list allows everyone
+pub struct IpAllowlist(Vec<IpNetwork>);
+
+impl IpAllowlist {
+    pub fn new(networks: Vec<IpNetwork>) -> Self {
+        Self(networks)
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // Clients on a dual-stack socket show up as IPv4-mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+        self.0.is_empty() || self.0.iter().any(|network| network.contains(ip))
+    }
+}
@@ -0,0 +1,60 @@
+use bytes::Bytes;
+use hyper::{HeaderMap, Response, StatusCode};
+use moka::sync::Cache;
+use std::time::Duration;
+use crate::types::{ResponseResult, full};
+use tracing::info;
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub etag: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl CachedResponse {
+    pub fn to_response(&self) -> ResponseResult {
+        let mut builder = Response::builder()
+            .status(StatusCode::OK);
+
+        for (k, v) in self.headers.iter() {
+            builder = builder.header(k, v);
+        }
+
+        Ok(builder.body(full(self.body.clone()))?)
+    }
+}
+
+// Cache of GET responses keyed by URL, used for ETag revalidation against the upstream
+pub struct ResponseCache {
+    entries: Cache<String, CachedResponse>,
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Cache::builder()
+                .time_to_idle(Duration::from_secs(60 * 60)) // 1 hour
+                .max_capacity(4096)
+                .build(),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Option<CachedResponse> {
+        self.entries.get(url)
+    }
+
+    pub fn store(&self, url: &str, headers: &HeaderMap, body: &Bytes) {
+        // Only responses carrying an ETag can be revalidated later
+        let Some(etag) = headers.get(hyper::header::ETAG).and_then(|v| v.to_str().ok()) else {
+            return;
+        };
+
+        info!(component = "CACHE", "Caching response for {} with ETag {}", url, etag);
+        self.entries.insert(url.to_string(), CachedResponse {
+            etag: etag.to_string(),
+            headers: headers.clone(),
+            body: body.clone(),
+        });
+    }
+}
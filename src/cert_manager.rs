@@ -1,83 +1,314 @@
 use boring2::{
     asn1::Asn1Time,
     bn::{BigNum, MsbOption},
+    ec::{EcGroup, EcKey},
     hash::MessageDigest,
     nid::Nid,
+    pkcs12::Pkcs12,
     pkey::{PKey, Private},
     rsa::Rsa,
+    symm::Cipher,
     x509::{
         extension::{BasicConstraints, KeyUsage, SubjectKeyIdentifier, AuthorityKeyIdentifier, ExtendedKeyUsage, SubjectAlternativeName},
         X509NameBuilder, X509,
     },
 };
 use rustls::{Certificate as RustlsCert, PrivateKey};
-use std::{fs, path::Path, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
-use moka::sync::Cache;
+use std::{fs, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use parking_lot::RwLock;
+use tokio::sync::Semaphore;
+use moka::{notification::RemovalCause, sync::Cache};
+use time::OffsetDateTime;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use crate::config::Config;
+use crate::metrics::MetricsRegistry;
+use tracing::{debug, info, warn};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-fn log(component: &str, message: &str) {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_millis();
-    println!("[{}][{}] {}", timestamp, component, message);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+// Subject common name of the CAs this proxy generates, a loaded CA with any other name was provisioned elsewhere
+const CA_COMMON_NAME: &str = "<BORING-PROXY CA>";
+
+// Key algorithm used for the CA and generated leaf certificates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    Rsa2048,
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+}
+
+impl KeyType {
+    fn generate(self) -> Result<PKey<Private>, Error> {
+        Ok(match self {
+            KeyType::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?)?,
+            KeyType::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?)?,
+            KeyType::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            KeyType::EcdsaP384 => {
+                let group = EcGroup::from_curve_name(Nid::SECP384R1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+        })
+    }
+
+    // Digest matching the key strength
+    fn digest(self) -> MessageDigest {
+        match self {
+            KeyType::EcdsaP384 => MessageDigest::sha384(),
+            _ => MessageDigest::sha256(),
+        }
+    }
+
+    fn is_rsa(self) -> bool {
+        matches!(self, KeyType::Rsa2048 | KeyType::Rsa4096)
+    }
+}
+
+// Leaf certificate chain and key, with the expiry reported by the admin API
+#[derive(Clone)]
+struct CachedCert {
+    chain: Vec<RustlsCert>,
+    key: PrivateKey,
+    not_after: OffsetDateTime,
+}
+
+impl CachedCert {
+    // Cache weight in bytes, so the capacity bounds memory rather than the number of hosts
+    fn weight(&self) -> u32 {
+        let bytes = self.chain.iter().map(|cert| cert.0.len()).sum::<usize>() + self.key.0.len();
+        bytes.try_into().unwrap_or(u32::MAX)
+    }
+}
+
+// CA certificate and key, replaced as a pair when the CA is renewed
+#[derive(Clone)]
+struct RootCa {
+    cert: Arc<X509>,
+    key: Arc<PKey<Private>>,
 }
 
 pub struct CertManager {
-    root_cert: Arc<X509>,
-    root_key: Arc<PKey<Private>>,
-    cert_cache: Cache<String, (Vec<RustlsCert>, PrivateKey)>,
+    root: RwLock<RootCa>,
+    // CAs the proxy did not generate itself, imported from PKCS#12 or provided as PEM files,
+    // belong to someone else's PKI and are never regenerated
+    ca_external: bool,
+    ca_cert_path: PathBuf,
+    ca_key_path: PathBuf,
+    ca_key_type: KeyType,
+    ca_key_password: Option<SecretString>,
+    // Renew the CA once it has fewer days than this left
+    ca_renew_days: u32,
+    ca_validity_days: u32,
+    leaf_validity_days: u32,
+    leaf_key_type: KeyType,
+    // With cert_key_reuse every leaf certificate carries this key. Generation then only costs a signature,
+    // but a key extracted from one intercepted connection (e.g. from a memory dump) can impersonate every
+    // host the proxy has issued a certificate for, not just one domain.
+    shared_leaf_key: Option<PKey<Private>>,
+    cert_cache: Cache<String, CachedCert>,
+    // Caps concurrent leaf key generations so a burst of new hosts can't take every core
+    keygen_permits: Arc<Semaphore>,
+    metrics: Arc<MetricsRegistry>,
 }
 
 impl CertManager {
-    pub fn new() -> Result<Self, Error> {
-        log("CERT", "Creating new certificate manager...");
-        
-        // Check for existing CA certificate and key
-        let ca_key_path = Path::new("ca.key");
-        let ca_cert_path = Path::new("ca.crt");
-        
-        let (root_cert, root_key) = if ca_key_path.exists() && ca_cert_path.exists() {
-            log("CERT", "Found existing CA certificate and key");
-            
-            // Load existing CA certificate and key
-            let cert_pem = fs::read(ca_cert_path)?;
-            let key_pem = fs::read(ca_key_path)?;
-            
-            let cert = X509::from_pem(&cert_pem)?;
-            let key = PKey::private_key_from_pem(&key_pem)?;
-            
-            log("CERT", "Successfully loaded existing CA certificate and key");
-            (cert, key)
+    pub fn new(config: &Config, metrics: Arc<MetricsRegistry>) -> Result<Self, Error> {
+        info!(component = "CERT", "Creating new certificate manager...");
+
+        // Cached certificates must be regenerated before they expire, so the TTL stays under the leaf validity
+        let leaf_validity = DAY * config.leaf_validity_days;
+        let default_ttl = leaf_validity.saturating_sub(DAY).max(Duration::from_secs(60 * 60));
+        let cache_ttl = match config.cert_cache_ttl_secs.map(Duration::from_secs) {
+            Some(ttl) if ttl >= leaf_validity => {
+                warn!(
+                    component = "CERT",
+                    "cert_cache_ttl_secs ({:?}) is not shorter than the {} day leaf validity, using {:?}",
+                    ttl, config.leaf_validity_days, default_ttl
+                );
+                default_ttl
+            }
+            Some(ttl) => ttl,
+            None => default_ttl,
+        };
+
+        // An imported PKCS#12 bundle takes priority over the PEM files
+        let (root_cert, root_key) = if let Some(p12_path) = &config.ca_p12 {
+            let password = config.ca_p12_password.as_ref().map(|p| p.expose_secret().as_str()).unwrap_or("");
+            Self::load_p12(p12_path, password)?
         } else {
-            log("CERT", "No existing CA certificate found, creating new one");
-            Self::create_root_ca()?
+            Self::load_or_create_root_ca(config)?
         };
         
-        log("CERT", "Certificate manager initialized successfully");
+        let shared_leaf_key = if config.cert_key_reuse {
+            info!(component = "CERT", "Generating one shared {:?} key for all leaf certificates", config.leaf_key_type);
+            Some(config.leaf_key_type.generate()?)
+        } else {
+            None
+        };
+
+        info!(component = "CERT", "Certificate manager initialized successfully");
         
         Ok(Self {
-            root_cert: Arc::new(root_cert),
-            root_key: Arc::new(root_key),
-            cert_cache: Cache::builder()
-                .time_to_live(Duration::from_secs(60 * 60 * 24 * 89)) // 89 days
-                .max_capacity(8096)
-                .build(),
+            root: RwLock::new(RootCa {
+                cert: Arc::new(root_cert),
+                key: Arc::new(root_key),
+            }),
+            ca_external: config.ca_p12.is_some() || !is_generated_ca(&root_cert),
+            ca_cert_path: config.ca_cert_path.clone(),
+            ca_key_path: config.ca_key_path.clone(),
+            ca_key_type: config.ca_key_type,
+            ca_key_password: config.ca_key_password.clone(),
+            ca_renew_days: config.ca_renew_days,
+            ca_validity_days: config.ca_validity_days,
+            leaf_validity_days: config.leaf_validity_days,
+            leaf_key_type: config.leaf_key_type,
+            shared_leaf_key,
+            cert_cache: Self::build_cache(cache_ttl, config.cert_cache_max_bytes, Arc::clone(&metrics)),
+            keygen_permits: Arc::new(Semaphore::new(config.cert_gen_parallelism.max(1))),
+            metrics,
         })
     }
 
-    fn create_root_ca() -> Result<(X509, PKey<Private>), Error> {
-        log("CERT", "Generating new CA certificate");
+    fn build_cache(ttl: Duration, max_bytes: u64, metrics: Arc<MetricsRegistry>) -> Cache<String, CachedCert> {
+        Cache::builder()
+            .time_to_live(ttl)
+            .weigher(|_, cached: &CachedCert| cached.weight())
+            .max_capacity(max_bytes)
+            .eviction_listener(move |domain: Arc<String>, cached: CachedCert, cause| {
+                let reason = match cause {
+                    RemovalCause::Expired => "expired",
+                    RemovalCause::Replaced => "replaced",
+                    RemovalCause::Size => "size",
+                    RemovalCause::Explicit => "explicit",
+                };
+                debug!(component = "CERT", "Evicted cached certificate for {} ({})", domain, reason);
+                metrics.cert_cache_evicted(reason, cached.weight());
+            })
+            .build()
+    }
+
+    fn load_or_create_root_ca(config: &Config) -> Result<(X509, PKey<Private>), Error> {
+        let ca_cert_path = config.ca_cert_path.as_path();
+        let ca_key_path = config.ca_key_path.as_path();
+
+        // Check for existing CA certificate and key
+        match (ca_cert_path.exists(), ca_key_path.exists()) {
+            (true, true) => {
+                info!(component = "CERT", "Found existing CA certificate {} and key {}", ca_cert_path.display(), ca_key_path.display());
+                let (cert, key) = Self::load_root_ca(ca_cert_path, ca_key_path, config.ca_key_password.as_ref())?;
+                info!(component = "CERT", "Successfully loaded existing CA certificate and key");
+                Ok((cert, key))
+            }
+            (false, false) => {
+                info!(component = "CERT", "No existing CA certificate found, creating new one");
+                Self::create_root_ca(
+                    ca_cert_path,
+                    ca_key_path,
+                    config.ca_key_type,
+                    config.ca_key_password.as_ref(),
+                    config.ca_validity_days,
+                )
+            }
+            // Never regenerate over half of a provisioned CA
+            (true, false) => Err(format!(
+                "CA certificate {} exists but key {} is missing", ca_cert_path.display(), ca_key_path.display()
+            ).into()),
+            (false, true) => Err(format!(
+                "CA key {} exists but certificate {} is missing", ca_key_path.display(), ca_cert_path.display()
+            ).into()),
+        }
+    }
+
+    // Load a CA from a PKCS#12 bundle, e.g. one exported from an organization's PKI
+    fn load_p12(path: &Path, password: &str) -> Result<(X509, PKey<Private>), Error> {
+        info!(component = "CERT", "Loading CA certificate and key from {}", path.display());
+        let der = fs::read(path)
+            .map_err(|e| format!("Failed to read PKCS#12 file {}: {}", path.display(), e))?;
+        let parsed = Pkcs12::from_der(&der)
+            .map_err(|e| format!("Failed to parse PKCS#12 file {}: {}", path.display(), e))?
+            .parse(password)
+            .map_err(|e| format!("Failed to decrypt PKCS#12 file {}, is the password correct? {}", path.display(), e))?;
+
+        // Leaf certificates signed by anything but a real CA would be rejected by clients
+        let cert_der = parsed.cert.to_der()?;
+        let (_, x509) = x509_parser::parse_x509_certificate(&cert_der)
+            .map_err(|e| format!("Failed to parse the certificate in {}: {}", path.display(), e))?;
+        let is_ca = x509.basic_constraints()
+            .map_err(|e| format!("Invalid BasicConstraints in {}: {}", path.display(), e))?
+            .is_some_and(|ext| ext.value.ca);
+        if !is_ca {
+            return Err(format!("Certificate in {} is not a CA (BasicConstraints CA:TRUE is missing)", path.display()).into());
+        }
+        let can_sign = x509.key_usage()
+            .map_err(|e| format!("Invalid KeyUsage in {}: {}", path.display(), e))?
+            .is_some_and(|ext| ext.value.key_cert_sign());
+        if !can_sign {
+            return Err(format!("Certificate in {} cannot sign certificates (KeyUsage keyCertSign is missing)", path.display()).into());
+        }
+
+        info!(component = "CERT", "Successfully loaded CA from {}", path.display());
+        Ok((parsed.cert, parsed.pkey))
+    }
+
+    fn load_root_ca(ca_cert_path: &Path, ca_key_path: &Path, password: Option<&SecretString>) -> Result<(X509, PKey<Private>), Error> {
+        let cert_pem = fs::read(ca_cert_path)
+            .map_err(|e| format!("Failed to read CA certificate {}: {}", ca_cert_path.display(), e))?;
+        let key_pem = fs::read(ca_key_path)
+            .map_err(|e| format!("Failed to read CA key {}: {}", ca_key_path.display(), e))?;
+
+        let cert = X509::from_pem(&cert_pem)
+            .map_err(|e| format!("Failed to parse CA certificate {} as PEM: {}", ca_cert_path.display(), e))?;
+        let key = match password {
+            Some(password) => PKey::private_key_from_pem_passphrase(&key_pem, password.expose_secret().as_bytes())
+                .map_err(|e| format!("Failed to decrypt CA key {}, is the password correct? {}", ca_key_path.display(), e))?,
+            None if is_encrypted_pem(&key_pem) => return Err(format!(
+                "{} requires a password but none is configured", ca_key_path.display()
+            ).into()),
+            None => Self::parse_private_key(&key_pem)
+                .ok_or_else(|| format!(
+                    "Failed to parse CA key {}: expected a PKCS#8 or traditional RSA/EC PEM private key",
+                    ca_key_path.display()
+                ))?,
+        };
+
+        if !cert.public_key()?.public_eq(&key) {
+            return Err(format!(
+                "CA key {} does not match certificate {}", ca_key_path.display(), ca_cert_path.display()
+            ).into());
+        }
+
+        Ok((cert, key))
+    }
+
+    // Accepts PKCS#8 ("PRIVATE KEY") as well as traditional "RSA PRIVATE KEY" / "EC PRIVATE KEY" PEM
+    fn parse_private_key(pem: &[u8]) -> Option<PKey<Private>> {
+        PKey::private_key_from_pem(pem).ok()
+            .or_else(|| Rsa::private_key_from_pem(pem).ok().and_then(|rsa| PKey::from_rsa(rsa).ok()))
+            .or_else(|| EcKey::private_key_from_pem(pem).ok().and_then(|ec| PKey::from_ec_key(ec).ok()))
+    }
+
+    fn create_root_ca(
+        ca_cert_path: &Path,
+        ca_key_path: &Path,
+        key_type: KeyType,
+        password: Option<&SecretString>,
+        validity_days: u32,
+    ) -> Result<(X509, PKey<Private>), Error> {
+        info!(component = "CERT", "Generating new {:?} CA certificate", key_type);
         
-        // Generate RSA key pair
-        let rsa = Rsa::generate(4096)?;
-        let privkey = PKey::from_rsa(rsa)?;
+        // Generate CA key pair
+        let privkey = key_type.generate()?;
 
         // Create CA certificate
         let mut name_builder = X509NameBuilder::new()?;
         name_builder.append_entry_by_nid(Nid::ORGANIZATIONNAME, "Boring Proxy")?;
-        name_builder.append_entry_by_nid(Nid::COMMONNAME, "<BORING-PROXY CA>")?;
+        name_builder.append_entry_by_nid(Nid::COMMONNAME, CA_COMMON_NAME)?;
         let name = name_builder.build();
 
         let mut builder = X509::builder()?;
@@ -96,7 +327,7 @@ impl CertManager {
         // Set validity period
         let not_before = Asn1Time::days_from_now(0)?;
         builder.set_not_before(&not_before)?;
-        let not_after = Asn1Time::days_from_now(90)?;
+        let not_after = Asn1Time::days_from_now(validity_days)?;
         builder.set_not_after(&not_after)?;
 
         // Add extensions
@@ -120,35 +351,93 @@ impl CertManager {
         builder.append_extension(subject_key_id)?;
 
         // Sign the certificate
-        builder.sign(&privkey, MessageDigest::sha256())?;
+        builder.sign(&privkey, key_type.digest())?;
         let cert = builder.build();
 
         // Save CA certificate and private key
-        let ca_cert_path = Path::new("ca.crt");
-        let ca_key_path = Path::new("ca.key");
-        log("CERT", "Saving new CA certificate and key");
+        info!(component = "CERT", "Saving new CA certificate to {} and key to {}", ca_cert_path.display(), ca_key_path.display());
         fs::write(ca_cert_path, cert.to_pem()?)?;
-        fs::write(ca_key_path, privkey.private_key_to_pem_pkcs8()?)?;
+        let key_pem = match password {
+            Some(password) => privkey.private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), password.expose_secret().as_bytes())?,
+            None => privkey.private_key_to_pem_pkcs8()?,
+        };
+        fs::write(ca_key_path, key_pem)?;
 
         Ok((cert, privkey))
     }
 
     pub fn get_ca_cert_pem(&self) -> Result<String, Error> {
-        Ok(String::from_utf8(self.root_cert.to_pem()?)?)
+        Ok(String::from_utf8(self.root.read().cert.to_pem()?)?)
     }
 
-    pub fn get_or_create_cert(&self, domain: &str) -> Result<(Vec<RustlsCert>, PrivateKey), Error> {
-        // Check cache first
-        if let Some(cert) = self.cert_cache.get(domain) {
-            log("CERT", &format!("Using cached certificate for {}", domain));
-            return Ok(cert);
+    pub fn get_ca_cert_der(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.root.read().cert.to_der()?)
+    }
+
+    // Regenerate the CA when it is about to expire. Every leaf certificate was signed by the old CA,
+    // so the cache is cleared as well. Returns whether the CA was renewed.
+    pub fn check_and_renew_ca(&self) -> Result<bool, Error> {
+        let now = Asn1Time::days_from_now(0)?;
+        let days_left = now.diff(self.root.read().cert.not_after())?.days;
+        if days_left >= self.ca_renew_days as i32 {
+            info!(component = "CERT", "CA certificate is valid for another {} days", days_left);
+            return Ok(false);
         }
 
-        log("CERT", &format!("Generating new certificate for {}", domain));
+        if self.ca_external {
+            warn!(component = "CERT", "Provided CA certificate expires in {} days, replace it with a renewed one", days_left);
+            return Ok(false);
+        }
 
-        // Generate RSA key pair
-        let rsa = Rsa::generate(4096)?;
-        let privkey = PKey::from_rsa(rsa)?;
+        warn!(component = "CERT", "CA certificate expires in {} days, generating a new one", days_left);
+        let (cert, key) = Self::create_root_ca(
+            &self.ca_cert_path,
+            &self.ca_key_path,
+            self.ca_key_type,
+            self.ca_key_password.as_ref(),
+            self.ca_validity_days,
+        )?;
+        *self.root.write() = RootCa {
+            cert: Arc::new(cert),
+            key: Arc::new(key),
+        };
+        self.cert_cache.invalidate_all();
+        warn!(
+            component = "CERT",
+            "CA certificate renewed, install the new {} in your browser/system again",
+            self.ca_cert_path.display()
+        );
+        Ok(true)
+    }
+
+    pub async fn get_or_create_cert(&self, host: &str) -> Result<(Vec<RustlsCert>, PrivateKey), Error> {
+        // Subdomains share the wildcard certificate of their parent domain
+        let domain = cert_domain(host);
+        let domain = domain.as_str();
+
+        // Check cache first
+        if let Some(cached) = self.cert_cache.get(domain) {
+            info!(component = "CERT", "Using cached certificate for {} (covers {})", domain, host);
+            self.metrics.cert_cache_hit();
+            return Ok((cached.chain, cached.key));
+        }
+        self.metrics.cert_cache_miss();
+
+        info!(component = "CERT", "Generating new {:?} certificate for {}", self.leaf_key_type, domain);
+        let root = self.root.read().clone();
+
+        // Generate leaf key pair off the runtime, the permit is held until generation finishes
+        let privkey = match &self.shared_leaf_key {
+            Some(key) => key.clone(),
+            None => {
+                let permit = Arc::clone(&self.keygen_permits).acquire_owned().await?;
+                let key_type = self.leaf_key_type;
+                tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    key_type.generate()
+                }).await??
+            }
+        };
 
         // Create leaf certificate
         let mut name_builder = X509NameBuilder::new()?;
@@ -166,13 +455,13 @@ impl CertManager {
         builder.set_serial_number(&serial_asn1)?;
 
         builder.set_subject_name(&name)?;
-        builder.set_issuer_name(self.root_cert.subject_name())?;
+        builder.set_issuer_name(root.cert.subject_name())?;
         builder.set_pubkey(&privkey)?;
 
         // Set validity period
         let not_before = Asn1Time::days_from_now(0)?;
         builder.set_not_before(&not_before)?;
-        let not_after = Asn1Time::days_from_now(90)?;
+        let not_after = Asn1Time::days_from_now(self.leaf_validity_days)?;
         builder.set_not_after(&not_after)?;
 
         // Add extensions
@@ -181,30 +470,34 @@ impl CertManager {
                 .build()?,
         )?;
 
-        builder.append_extension(
-            KeyUsage::new()
-                .critical()
-                .non_repudiation()
-                .digital_signature()
-                .key_encipherment()
-                .build()?,
-        )?;
+        // Key encipherment only applies to RSA key exchange
+        let mut key_usage = KeyUsage::new();
+        key_usage.critical().non_repudiation().digital_signature();
+        if self.leaf_key_type.is_rsa() {
+            key_usage.key_encipherment();
+        }
+        builder.append_extension(key_usage.build()?)?;
 
         let subject_key_id = SubjectKeyIdentifier::new()
-            .build(&builder.x509v3_context(Some(&self.root_cert), None))?;
+            .build(&builder.x509v3_context(Some(&root.cert), None))?;
         builder.append_extension(subject_key_id)?;
 
         let auth_key_id = AuthorityKeyIdentifier::new()
             .keyid(false)
             .issuer(false)
-            .build(&builder.x509v3_context(Some(&self.root_cert), None))?;
+            .build(&builder.x509v3_context(Some(&root.cert), None))?;
         builder.append_extension(auth_key_id)?;
 
         // Add subject alternative names
+        // IP addresses need an iPAddress entry, a dNSName or wildcard for them is rejected by browsers
         let mut san = SubjectAlternativeName::new();
-        san.dns(domain);
-        san.dns(&format!("*.{}", domain));
-        let san = san.build(&builder.x509v3_context(Some(&self.root_cert), None))?;
+        if domain.parse::<std::net::IpAddr>().is_ok() {
+            san.ip(domain);
+        } else {
+            san.dns(domain);
+            san.dns(&format!("*.{}", domain));
+        }
+        let san = san.build(&builder.x509v3_context(Some(&root.cert), None))?;
         builder.append_extension(san)?;
 
         // Add extended key usage
@@ -215,20 +508,99 @@ impl CertManager {
         builder.append_extension(extended_key_usage)?;
 
         // Sign with CA key
-        builder.sign(&self.root_key, MessageDigest::sha256())?;
+        builder.sign(&root.key, self.leaf_key_type.digest())?;
         let cert = builder.build();
 
         // Create certificate chain
         let cert_chain = vec![
             RustlsCert(cert.to_der()?),
-            RustlsCert(self.root_cert.to_der()?),
+            RustlsCert(root.cert.to_der()?),
         ];
-        let key = PrivateKey(privkey.private_key_to_der()?);
+        // PKCS#8 DER is understood by rustls for both RSA and EC keys
+        let key = PrivateKey(privkey.private_key_to_der_pkcs8()?);
 
         // Cache the certificate
-        log("CERT", &format!("Caching certificate for {}", domain));
-        self.cert_cache.insert(domain.to_string(), (cert_chain.clone(), key.clone()));
+        info!(component = "CERT", "Caching certificate for {}", domain);
+        let cached = CachedCert {
+            chain: cert_chain.clone(),
+            key: key.clone(),
+            not_after: OffsetDateTime::now_utc() + time::Duration::days(self.leaf_validity_days as i64),
+        };
+        // The eviction listener takes the same weight off again, including for a replaced entry
+        self.metrics.cert_cached(cached.weight());
+        self.cert_cache.insert(domain.to_string(), cached);
 
         Ok((cert_chain, key))
     }
+
+    // Domains with a cached leaf certificate and when each certificate expires
+    pub fn cached_certs(&self) -> Vec<(String, OffsetDateTime)> {
+        self.cert_cache.iter()
+            .map(|(domain, cached)| (domain.to_string(), cached.not_after))
+            .collect()
+    }
+
+    pub fn evict_cert(&self, domain: &str) -> bool {
+        self.cert_cache.remove(domain).is_some()
+    }
+}
+
+fn is_generated_ca(cert: &X509) -> bool {
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .any(|entry| entry.data().as_slice() == CA_COMMON_NAME.as_bytes())
+}
+
+// Encrypted PKCS#8 keys use their own PEM label, traditional keys mark it in a Proc-Type header
+fn is_encrypted_pem(pem: &[u8]) -> bool {
+    let pem = String::from_utf8_lossy(pem);
+    pem.contains("ENCRYPTED PRIVATE KEY") || pem.contains("Proc-Type: 4,ENCRYPTED")
+}
+
+// Multi-label public suffixes under which the registrable domain has three labels
+const MULTI_LABEL_SUFFIXES: &[&str] = &[
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "me.uk", "net.uk",
+    "com.au", "net.au", "org.au", "edu.au", "gov.au",
+    "co.nz", "org.nz", "co.za", "co.jp", "ne.jp", "or.jp", "ac.jp",
+    "co.kr", "or.kr", "co.in", "net.in", "org.in", "co.il",
+    "com.br", "net.br", "org.br", "com.cn", "net.cn", "org.cn",
+    "com.hk", "com.tw", "com.sg", "com.mx", "com.ar", "com.tr",
+];
+
+// Registrable base domain of a host, e.g. "www.example.co.uk" -> "example.co.uk"
+fn base_domain(host: &str) -> &str {
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() < 3 {
+        return host;
+    }
+
+    let suffix = labels[labels.len() - 2..].join(".");
+    let base_labels = if MULTI_LABEL_SUFFIXES.contains(&suffix.as_str()) { 3 } else { 2 };
+    if labels.len() <= base_labels {
+        return host;
+    }
+
+    let skip: usize = labels[..labels.len() - base_labels].iter().map(|l| l.len() + 1).sum();
+    &host[skip..]
+}
+
+// Domain to issue the certificate for. A certificate for D carries SANs for D and *.D,
+// so any host one label below its parent can reuse the parent's certificate.
+fn cert_domain(host: &str) -> String {
+    // CONNECT authorities carry IPv6 literals in brackets, the SAN needs the bare address
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    let host = host.trim_end_matches('.').to_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+
+    let base = base_domain(&host);
+    if host == base {
+        return host;
+    }
+
+    match host.split_once('.') {
+        Some((_, parent)) => parent.to_string(),
+        None => host,
+    }
 }
@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use bytes::Bytes;
+use hyper::header::HeaderValue;
+use parking_lot::RwLock;
+use rquest::cookie::CookieStore as RqCookieStore;
+use url::Url;
+use crate::types::Error;
+
+// Cookie jar backed by cookie_store so its contents can be written to and read from disk
+#[derive(Default)]
+pub struct CookieJar {
+    store: RwLock<cookie_store::CookieStore>,
+    // Set when the upstream stores a cookie, so periodic saves skip jars that are already on disk
+    changed: AtomicBool,
+}
+
+impl CookieJar {
+    // Expired cookies are dropped while loading
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open cookie file {}: {}", path.display(), e))?;
+        let store = cookie_store::serde::json::load(BufReader::new(file))
+            .map_err(|e| format!("Invalid cookie file {}: {}", path.display(), e))?;
+        Ok(Self { store: RwLock::new(store), changed: AtomicBool::new(false) })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let store = cookie_store::serde::json::load(json.as_bytes())
+            .map_err(|e| format!("Invalid saved cookies: {}", e))?;
+        Ok(Self { store: RwLock::new(store), changed: AtomicBool::new(false) })
+    }
+
+    // Session cookies are kept too, a restarted proxy should look like the same browser session
+    pub fn to_json(&self) -> Result<String, Error> {
+        let mut buffer = Vec::new();
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(&self.store.read(), &mut buffer)
+            .map_err(|e| format!("Failed to serialize cookies: {}", e))?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create cookie file {}: {}", path.display(), e))?;
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(&self.store.read(), &mut BufWriter::new(file))
+            .map_err(|e| format!("Failed to write cookie file {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    // Whether a cookie was stored since the last call
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl RqCookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|v| std::str::from_utf8(v.as_bytes()).ok())
+            .filter_map(|v| cookie::Cookie::parse(v.to_owned()).ok());
+        self.store.write().store_response_cookies(cookies, url);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let header = self.store.read()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_maybe_shared(Bytes::from(header)).ok()
+    }
+}
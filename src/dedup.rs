@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use bytes::Bytes;
+use hyper::{body::Incoming, HeaderMap, Method, Request, Response, StatusCode};
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+use crate::types::{Error, ResponseResult, full};
+
+// Request headers that change what the upstream returns for a user, requests differing in any of them
+// are never merged
+const KEY_HEADERS: &[hyper::header::HeaderName] = &[
+    hyper::header::ACCEPT,
+    hyper::header::AUTHORIZATION,
+    hyper::header::COOKIE,
+    hyper::header::RANGE,
+];
+
+// A finished response handed to every request that waited on it
+#[derive(Clone)]
+pub struct SharedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl SharedResponse {
+    pub fn to_response(&self) -> ResponseResult {
+        let mut response = Response::builder().status(self.status);
+        for (k, v) in &self.headers {
+            response = response.header(k, v);
+        }
+        Ok(response.body(full(self.body.clone()))?)
+    }
+}
+
+// What waiting requests get: the response, or the status and message of the leader's error
+type Outcome = Result<SharedResponse, (StatusCode, &'static str)>;
+
+pub enum Inflight<'a> {
+    // First request for the key, it goes upstream and must finish() with the result
+    Leader(InflightGuard<'a>),
+    // An identical request is already upstream, wait for its outcome
+    Follower(broadcast::Receiver<Outcome>),
+}
+
+// Identical idempotent requests that are currently upstream, so concurrent duplicates share one exchange
+#[derive(Default)]
+pub struct InflightRequests {
+    inflight: Mutex<HashMap<u64, broadcast::Sender<Outcome>>>,
+}
+
+impl InflightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Key for GET, HEAD and OPTIONS requests, None for everything else
+    pub fn key(req: &Request<Incoming>) -> Option<u64> {
+        if ![Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method()) {
+            return None;
+        }
+        // Event streams never finish, so there would be no response to share
+        let event_stream = req.headers().get(hyper::header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/event-stream"));
+        if event_stream {
+            return None;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        req.method().hash(&mut hasher);
+        req.uri().to_string().hash(&mut hasher);
+        for name in KEY_HEADERS {
+            for value in req.headers().get_all(name) {
+                name.hash(&mut hasher);
+                value.as_bytes().hash(&mut hasher);
+            }
+        }
+        Some(hasher.finish())
+    }
+
+    pub fn join(&self, key: u64) -> Inflight<'_> {
+        let mut inflight = self.inflight.lock();
+        if let Some(sender) = inflight.get(&key) {
+            return Inflight::Follower(sender.subscribe());
+        }
+
+        let (sender, _) = broadcast::channel(1);
+        inflight.insert(key, sender.clone());
+        Inflight::Leader(InflightGuard { requests: self, key, sender })
+    }
+
+    // Only removes the entry when it still belongs to this sender, a new leader may have taken the key since
+    fn remove(&self, key: u64, sender: &broadcast::Sender<Outcome>) {
+        let mut inflight = self.inflight.lock();
+        if inflight.get(&key).is_some_and(|current| current.same_channel(sender)) {
+            inflight.remove(&key);
+        }
+    }
+}
+
+// Held by the leader, dropping it without finish() (e.g. the client went away) closes the channel
+// and the followers fail
+pub struct InflightGuard<'a> {
+    requests: &'a InflightRequests,
+    key: u64,
+    sender: broadcast::Sender<Outcome>,
+}
+
+impl InflightGuard<'_> {
+    // Requests arriving after this start their own exchange
+    pub fn finish(self, outcome: Outcome) {
+        self.requests.remove(self.key, &self.sender);
+        let _ = self.sender.send(outcome);
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        self.requests.remove(self.key, &self.sender);
+    }
+}
+
+// Turn what a follower received into its response
+pub fn follower_response(outcome: Result<Outcome, broadcast::error::RecvError>) -> ResponseResult {
+    match outcome {
+        Ok(Ok(shared)) => shared.to_response(),
+        Ok(Err((status, message))) => Ok(Response::builder()
+            .status(status)
+            .body(full(message))?),
+        Err(_) => Err::<_, Error>("Deduplicated request ended without a response".into()),
+    }
+}
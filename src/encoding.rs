@@ -0,0 +1,75 @@
+use std::io::{Read, Write};
+use serde::Deserialize;
+use crate::types::Error;
+
+// Content-Encoding values the proxy can decode and produce
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Br,
+    Zstd,
+}
+
+impl ContentEncoding {
+    pub fn from_header(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "identity" => Some(Self::Identity),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "deflate" => Some(Self::Deflate),
+            "br" => Some(Self::Br),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Br => "br",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut decoded = Vec::new();
+        match self {
+            Self::Identity => decoded.extend_from_slice(data),
+            Self::Gzip => { flate2::read::GzDecoder::new(data).read_to_end(&mut decoded)?; }
+            // HTTP deflate is the zlib format, not raw deflate
+            Self::Deflate => { flate2::read::ZlibDecoder::new(data).read_to_end(&mut decoded)?; }
+            Self::Br => { brotli::Decompressor::new(data, 4096).read_to_end(&mut decoded)?; }
+            Self::Zstd => decoded = zstd::decode_all(data)?,
+        }
+        Ok(decoded)
+    }
+
+    pub fn encode(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(match self {
+            Self::Identity => data.to_vec(),
+            Self::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Self::Deflate => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Self::Br => {
+                let mut encoded = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+                    encoder.write_all(data)?;
+                }
+                encoded
+            }
+            Self::Zstd => zstd::encode_all(data, 0)?,
+        })
+    }
+}
@@ -0,0 +1,289 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use boring2::hash::{hash, MessageDigest};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use crate::types::Error;
+
+const HANDSHAKE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+const SUPPORTED_GROUPS: u16 = 10;
+const EC_POINT_FORMATS: u16 = 11;
+// How long a client gets to send its ClientHello record before it is handed to rustls unread
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Cursor over the length-prefixed fields of a handshake message
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err("Truncated ClientHello".into());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(3)?;
+        Ok(usize::from(bytes[0]) << 16 | usize::from(bytes[1]) << 8 | usize::from(bytes[2]))
+    }
+
+    fn vec8(&mut self) -> Result<Reader<'a>, Error> {
+        let len = usize::from(self.u8()?);
+        Ok(Reader { data: self.take(len)? })
+    }
+
+    fn vec16(&mut self) -> Result<Reader<'a>, Error> {
+        let len = usize::from(self.u16()?);
+        Ok(Reader { data: self.take(len)? })
+    }
+
+    fn u16_list(mut self) -> Result<Vec<u16>, Error> {
+        let mut values = Vec::new();
+        while !self.data.is_empty() {
+            values.push(self.u16()?);
+        }
+        Ok(values)
+    }
+}
+
+// GREASE values (RFC 8701) are random per connection, JA3 leaves them out
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn join(values: impl IntoIterator<Item = u16>) -> String {
+    values.into_iter()
+        .filter(|v| !is_grease(*v))
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// JA3 fingerprint of a TLS ClientHello: version, ciphers, extensions, curves and point formats
+// joined as decimal lists, and their MD5
+pub struct Ja3Fingerprint {
+    pub text: String,
+    pub hash: String,
+}
+
+impl Ja3Fingerprint {
+    // Takes the TLS record carrying the ClientHello
+    pub fn compute(client_hello: &[u8]) -> Result<Self, Error> {
+        let mut record = Reader { data: client_hello };
+        if record.u8()? != HANDSHAKE {
+            return Err("Not a TLS handshake record".into());
+        }
+        record.take(2)?;
+        let mut record = record.vec16()?;
+
+        if record.u8()? != CLIENT_HELLO {
+            return Err("Not a ClientHello".into());
+        }
+        let len = record.u24()?;
+        let mut hello = Reader { data: record.take(len)? };
+
+        let version = hello.u16()?;
+        hello.take(32)?;
+        hello.vec8()?;
+        let ciphers = hello.vec16()?.u16_list()?;
+        hello.vec8()?;
+
+        let (mut extensions, mut groups, mut point_formats) = (Vec::new(), Vec::new(), Vec::new());
+        if !hello.data.is_empty() {
+            let mut list = hello.vec16()?;
+            while !list.data.is_empty() {
+                let extension = list.u16()?;
+                let mut data = list.vec16()?;
+                match extension {
+                    SUPPORTED_GROUPS => groups = data.vec16()?.u16_list()?,
+                    EC_POINT_FORMATS => point_formats = data.vec8()?.data.iter().map(|f| u16::from(*f)).collect(),
+                    _ => {}
+                }
+                extensions.push(extension);
+            }
+        }
+
+        let text = format!(
+            "{},{},{},{},{}",
+            version, join(ciphers), join(extensions), join(groups), join(point_formats)
+        );
+        let hash = hash(MessageDigest::md5(), text.as_bytes())?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Ok(Self { text, hash })
+    }
+}
+
+// Append what the client sends to `buf` until the first TLS record is complete and return its length.
+// None when the stream doesn't start with a handshake record or ends before the record does.
+async fn read_record<T: AsyncRead + Unpin>(io: &mut T, buf: &mut Vec<u8>) -> io::Result<Option<usize>> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        if buf.first().is_some_and(|b| *b != HANDSHAKE) {
+            return Ok(None);
+        }
+        if buf.len() >= 5 {
+            let len = 5 + usize::from(u16::from_be_bytes([buf[3], buf[4]]));
+            if buf.len() >= len {
+                return Ok(Some(len));
+            }
+        }
+        let n = io.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+// Read the first TLS record, which carries the ClientHello, and return a stream that replays everything
+// read. The record is None when the client sent something else or took too long, rustls then gets the
+// same bytes and fails or carries on as it would have.
+pub async fn read_client_hello<T: AsyncRead + Unpin>(mut io: T) -> io::Result<(Option<Bytes>, Rewind<T>)> {
+    let mut buf = Vec::new();
+    let len = match tokio::time::timeout(CLIENT_HELLO_TIMEOUT, read_record(&mut io, &mut buf)).await {
+        Ok(result) => result?,
+        Err(_) => None,
+    };
+
+    let read = Bytes::from(buf);
+    let record = len.map(|len| read.slice(..len));
+    Ok((record, Rewind { prefix: read, inner: io }))
+}
+
+// Stream that yields bytes already read from the inner stream before reading from it again
+pub struct Rewind<T> {
+    prefix: Bytes,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    // Nothing read ahead, reads go straight to the inner stream
+    pub fn new(inner: T) -> Self {
+        Self { prefix: Bytes::new(), inner }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Rewind<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.prefix.is_empty() {
+            let len = this.prefix.len().min(buf.remaining());
+            buf.put_slice(&this.prefix.split_to(len));
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Rewind<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_vec(values: &[u16]) -> Vec<u8> {
+        let mut out = ((values.len() * 2) as u16).to_be_bytes().to_vec();
+        values.iter().for_each(|v| out.extend_from_slice(&v.to_be_bytes()));
+        out
+    }
+
+    // TLS record carrying a ClientHello with the given fields, extensions as (type, data) pairs
+    fn client_hello(version: u16, ciphers: &[u16], extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut hello = version.to_be_bytes().to_vec();
+        hello.extend_from_slice(&[0x42; 32]);
+        hello.push(0);
+        hello.extend(u16_vec(ciphers));
+        hello.extend_from_slice(&[1, 0]);
+
+        let mut list = Vec::new();
+        for (extension, data) in extensions {
+            list.extend_from_slice(&extension.to_be_bytes());
+            list.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            list.extend_from_slice(data);
+        }
+        hello.extend_from_slice(&(list.len() as u16).to_be_bytes());
+        hello.extend(list);
+
+        let mut handshake = vec![CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(hello);
+
+        let mut record = vec![HANDSHAKE, 3, 1];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend(handshake);
+        record
+    }
+
+    // The example from the JA3 README, with GREASE values a current browser would add mixed in
+    #[test]
+    fn published_ja3_example() {
+        let server_name = [&[0, 14, 0, 0, 11][..], b"example.com"].concat();
+        let record = client_hello(
+            769,
+            &[0x0a0a, 47, 53, 5, 10, 49161, 49162, 49171, 49172, 50, 56, 19, 4],
+            &[
+                (0xdada, Vec::new()),
+                (0, server_name),
+                (SUPPORTED_GROUPS, u16_vec(&[0x4a4a, 23, 24, 25])),
+                (EC_POINT_FORMATS, vec![1, 0]),
+            ],
+        );
+
+        let ja3 = Ja3Fingerprint::compute(&record).unwrap();
+        assert_eq!(ja3.text, "769,47-53-5-10-49161-49162-49171-49172-50-56-19-4,0-10-11,23-24-25,0");
+        assert_eq!(ja3.hash, "ada70206e40642a3e4461f35503241d5");
+    }
+
+    #[test]
+    fn grease_values() {
+        assert!([0x0a0a, 0x1a1a, 0x4a4a, 0xdada, 0xfafa].into_iter().all(is_grease));
+        assert!(![0x0a1a, 0x000a, 0x0a0b, 23, 49195].into_iter().any(is_grease));
+    }
+
+    #[test]
+    fn rejects_other_records() {
+        let mut record = client_hello(771, &[4865], &[]);
+        record[5] = 2;
+        assert!(Ja3Fingerprint::compute(&record).is_err());
+        assert!(Ja3Fingerprint::compute(&[23, 3, 3, 0, 0]).is_err());
+        assert!(Ja3Fingerprint::compute(&client_hello(771, &[4865], &[])[..20]).is_err());
+    }
+}
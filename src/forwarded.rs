@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+// Which forwarding headers are added to upstream requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeaderMode {
+    #[default]
+    Off,
+    // X-Forwarded-For, X-Forwarded-Host and X-Forwarded-Proto
+    Legacy,
+    // RFC 7239 Forwarded
+    Standard,
+    Both,
+}
+
+// What to do when the client already sent forwarding headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExistingForwarded {
+    // Add this hop after the existing ones
+    #[default]
+    Append,
+    // Drop what the client sent and describe only this hop
+    Replace,
+}
+
+// Details about the hop being added
+pub struct ForwardedHop<'a> {
+    pub client: IpAddr,
+    pub host: &'a str,
+    pub proto: &'a str,
+    pub by: IpAddr,
+}
+
+// IPv6 nodes must be bracketed and quoted in the Forwarded header
+fn forwarded_node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    }
+}
+
+// Host values may contain characters that are not valid tokens, e.g. the port separator
+fn forwarded_value(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn set_header(headers: &mut HeaderMap, name: HeaderName, existing: ExistingForwarded, value: String, separator: &str) {
+    let value = match (existing, headers.get(&name).and_then(|v| v.to_str().ok())) {
+        (ExistingForwarded::Append, Some(current)) => format!("{}{}{}", current, separator, value),
+        _ => value,
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+pub fn apply(headers: &mut HeaderMap, mode: ForwardedHeaderMode, existing: ExistingForwarded, hop: &ForwardedHop<'_>) {
+    if matches!(mode, ForwardedHeaderMode::Legacy | ForwardedHeaderMode::Both) {
+        set_header(headers, X_FORWARDED_FOR, existing, hop.client.to_string(), ", ");
+
+        // Host and proto describe the original request, so an earlier hop's values are kept when appending
+        for (name, value) in [(X_FORWARDED_HOST, hop.host), (X_FORWARDED_PROTO, hop.proto)] {
+            if existing == ExistingForwarded::Replace || !headers.contains_key(&name) {
+                set_header(headers, name, ExistingForwarded::Replace, value.to_string(), "");
+            }
+        }
+    }
+
+    if matches!(mode, ForwardedHeaderMode::Standard | ForwardedHeaderMode::Both) {
+        let element = format!(
+            "for={};host={};proto={};by={}",
+            forwarded_node(hop.client),
+            forwarded_value(hop.host),
+            hop.proto,
+            forwarded_node(hop.by),
+        );
+        set_header(headers, hyper::header::FORWARDED, existing, element, ", ");
+    }
+}
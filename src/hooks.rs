@@ -0,0 +1,135 @@
+use bytes::Bytes;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::{request, response};
+use regex::Regex;
+use std::borrow::Cow;
+use tokio_tungstenite::tungstenite::Message;
+use crate::encoding::ContentEncoding;
+use crate::types::Error;
+
+// Rewrites a request after its body has been collected and before it is sent upstream
+pub trait RequestHook {
+    fn modify_request(&self, req: &mut request::Parts, body: &mut Bytes) -> Result<(), Error>;
+}
+
+// Rewrites an upstream response before it is returned to the client
+pub trait ResponseHook {
+    fn modify_response(&self, res: &mut response::Parts, body: &mut Bytes) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsDirection {
+    ClientToServer,
+    ServerToClient,
+}
+
+// Rewrites a WebSocket message before it is relayed. An error ends the tunnel,
+// e.g. to cut a connection off when a certain close frame is seen.
+pub trait WsHook {
+    fn on_message(&self, direction: WsDirection, msg: &mut Message) -> Result<(), Error>;
+}
+
+// Adds a fixed header to every response, e.g. X-Boring-Proxy: 1
+pub struct HeaderInjectHook {
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl HeaderInjectHook {
+    pub fn new(name: &str, value: &str) -> Result<Self, Error> {
+        Ok(Self {
+            name: HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid header name {}: {}", name, e))?,
+            value: HeaderValue::from_str(value)
+                .map_err(|e| format!("Invalid value for header {}: {}", name, e))?,
+        })
+    }
+}
+
+impl ResponseHook for HeaderInjectHook {
+    fn modify_response(&self, res: &mut response::Parts, _body: &mut Bytes) -> Result<(), Error> {
+        res.headers.insert(self.name.clone(), self.value.clone());
+        Ok(())
+    }
+}
+
+// Decodes responses whose Content-Type matches and encodes them again, e.g. identity to drop gzip
+// from small JSON responses or br to shrink large text ones. Unknown encodings are left alone.
+pub struct RecompressHook {
+    content_type: Regex,
+    encoding: ContentEncoding,
+}
+
+impl RecompressHook {
+    pub fn new(content_type_pattern: &str, encoding: ContentEncoding) -> Result<Self, Error> {
+        Ok(Self {
+            content_type: Regex::new(content_type_pattern)
+                .map_err(|e| format!("Invalid recompress content type pattern {}: {}", content_type_pattern, e))?,
+            encoding,
+        })
+    }
+}
+
+impl ResponseHook for RecompressHook {
+    fn modify_response(&self, res: &mut response::Parts, body: &mut Bytes) -> Result<(), Error> {
+        let matches = res.headers.get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|content_type| self.content_type.is_match(content_type));
+        if !matches || body.is_empty() {
+            return Ok(());
+        }
+
+        // Encodings are listed in the order they were applied, so undo them from the last one
+        let header = res.headers.get(hyper::header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let Some(applied) = header.split(',').map(ContentEncoding::from_header).collect::<Option<Vec<_>>>() else {
+            return Ok(());
+        };
+        let applied = applied.into_iter()
+            .filter(|encoding| *encoding != ContentEncoding::Identity)
+            .collect::<Vec<_>>();
+        if applied.as_slice() == [self.encoding] || (applied.is_empty() && self.encoding == ContentEncoding::Identity) {
+            return Ok(());
+        }
+
+        let mut decoded = body.to_vec();
+        for encoding in applied.iter().rev() {
+            decoded = encoding.decode(&decoded)?;
+        }
+        *body = Bytes::from(self.encoding.encode(&decoded)?);
+
+        match self.encoding {
+            ContentEncoding::Identity => { res.headers.remove(hyper::header::CONTENT_ENCODING); }
+            encoding => { res.headers.insert(hyper::header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str())); }
+        }
+        Ok(())
+    }
+}
+
+// Replaces every match of a pattern in text frames going either way
+pub struct WsTextRewriteHook {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl WsTextRewriteHook {
+    pub fn new(pattern: &str, replacement: &str) -> Result<Self, Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)
+                .map_err(|e| format!("Invalid WebSocket rewrite pattern {}: {}", pattern, e))?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+impl WsHook for WsTextRewriteHook {
+    fn on_message(&self, _direction: WsDirection, msg: &mut Message) -> Result<(), Error> {
+        if let Message::Text(text) = msg {
+            if let Cow::Owned(rewritten) = self.pattern.replace_all(text, self.replacement.as_str()) {
+                *text = rewritten;
+            }
+        }
+        Ok(())
+    }
+}
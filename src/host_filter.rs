@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use crate::matcher::DomainMatcher;
+use crate::types::Error;
+use tracing::info;
+
+// Hostnames and glob patterns loaded from a file with one entry per line
+pub struct HostList {
+    path: PathBuf,
+    matcher: DomainMatcher,
+}
+
+impl HostList {
+    // Entries are exact hostnames or globs like *.doubleclick.net, blank lines and # comments are skipped
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read host list {}: {}", path.display(), e))?;
+
+        let matcher = DomainMatcher::new(contents.lines(), &path.display().to_string())?;
+
+        info!(component = "BLOCK", "Loaded {} hosts and {} patterns from {}", matcher.host_count(), matcher.pattern_count(), path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            matcher,
+        })
+    }
+
+    fn reload(&self) -> Result<Self, Error> {
+        Self::load(&self.path)
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        self.matcher.matches(host)
+    }
+}
+
+// Decides which destination hosts the proxy forwards to
+pub struct HostFilter {
+    // When set, only matching hosts are allowed
+    allow: Option<HostList>,
+    block: Option<HostList>,
+}
+
+impl HostFilter {
+    pub fn load(allowlist: Option<&Path>, blocklist: Option<&Path>) -> Result<Self, Error> {
+        Ok(Self {
+            allow: allowlist.map(HostList::load).transpose()?,
+            block: blocklist.map(HostList::load).transpose()?,
+        })
+    }
+
+    // Read both files again, used when the proxy receives SIGHUP
+    pub fn reload(&self) -> Result<Self, Error> {
+        Ok(Self {
+            allow: self.allow.as_ref().map(HostList::reload).transpose()?,
+            block: self.block.as_ref().map(HostList::reload).transpose()?,
+        })
+    }
+
+    // Block entries win over allow entries, so a broad allow like *.example.com can have exceptions
+    pub fn is_blocked(&self, host: &str) -> bool {
+        if self.block.as_ref().is_some_and(|block| block.matches(host)) {
+            return true;
+        }
+        self.allow.as_ref().is_some_and(|allow| !allow.matches(host))
+    }
+}
+
+// Hosts whose CONNECT tunnels are spliced through untouched instead of intercepted, for sites that pin
+// their certificates. Entries are exact hostnames or globs like *.bank.example.
+#[derive(Default)]
+pub struct PassthroughList {
+    matcher: DomainMatcher,
+}
+
+impl PassthroughList {
+    pub fn new(entries: &[String]) -> Result<Self, Error> {
+        let matcher = DomainMatcher::new(entries.iter().map(String::as_str), "passthrough_hosts")?;
+        if !matcher.is_empty() {
+            info!(component = "PROXY", "Passing through TLS for {} hosts and {} patterns", matcher.host_count(), matcher.pattern_count());
+        }
+        Ok(Self { matcher })
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        self.matcher.matches(host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write `entries` to a fresh host list file
+    fn host_list(name: &str, entries: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("boring-proxy-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, entries).unwrap();
+        path
+    }
+
+    #[test]
+    fn block_beats_allow() {
+        let allow = host_list("allow-broad", "*.example.com\n");
+        let block = host_list("block-narrow", "ads.example.com\n");
+        let filter = HostFilter::load(Some(&allow), Some(&block)).unwrap();
+        assert!(filter.is_blocked("ads.example.com"));
+        assert!(!filter.is_blocked("www.example.com"));
+    }
+
+    #[test]
+    fn allowlist_rejects_everything_else() {
+        let allow = host_list("allow-only", "api.example.com\n*.cdn.example.net\n");
+        let filter = HostFilter::load(Some(&allow), None).unwrap();
+        assert!(!filter.is_blocked("api.example.com"));
+        assert!(!filter.is_blocked("img.cdn.example.net"));
+        assert!(filter.is_blocked("example.com"));
+        assert!(filter.is_blocked("www.example.org"));
+    }
+
+    #[test]
+    fn empty_lists() {
+        assert!(!HostFilter::load(None, None).unwrap().is_blocked("example.com"));
+
+        let block = host_list("block-empty", "");
+        assert!(!HostFilter::load(None, Some(&block)).unwrap().is_blocked("example.com"));
+
+        // An allowlist without entries allows nothing, rather than silently turning the restriction off
+        let allow = host_list("allow-empty", "# nothing yet\n");
+        assert!(HostFilter::load(Some(&allow), Some(&block)).unwrap().is_blocked("example.com"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde::Deserialize;
+use crate::types::Error;
+use tracing::info;
+
+// Chromium's preload list, the same file browsers build their HSTS list from
+const PRELOAD_LIST_URL: &str =
+    "https://raw.githubusercontent.com/chromium/chromium/main/net/http/transport_security_state_static.json";
+
+#[derive(Deserialize)]
+struct PreloadList {
+    entries: Vec<PreloadEntry>,
+}
+
+#[derive(Deserialize)]
+struct PreloadEntry {
+    name: String,
+    #[serde(default)]
+    include_subdomains: bool,
+    mode: Option<String>,
+}
+
+// Hosts browsers only ever reach over HTTPS, keyed by name with whether subdomains are covered too
+pub struct HstsPreload {
+    hosts: HashMap<String, bool>,
+}
+
+impl HstsPreload {
+    // Load the list from a local copy, or download it when no file is given
+    pub async fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let contents = match path {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read HSTS preload list {}: {}", path.display(), e))?,
+            None => {
+                info!(component = "PROXY", "Downloading HSTS preload list from {}", PRELOAD_LIST_URL);
+                rquest::Client::new().get(PRELOAD_LIST_URL).send().await?
+                    .error_for_status()?
+                    .text().await?
+            }
+        };
+        Self::parse(&contents)
+    }
+
+    fn parse(contents: &str) -> Result<Self, Error> {
+        // The file is JSON with // comment lines
+        let json = contents.lines()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let list: PreloadList = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid HSTS preload list: {}", e))?;
+
+        let hosts = list.entries.into_iter()
+            .filter(|entry| entry.mode.as_deref() == Some("force-https"))
+            .map(|entry| (entry.name.to_lowercase(), entry.include_subdomains))
+            .collect::<HashMap<_, _>>();
+        info!(component = "PROXY", "Loaded {} HSTS preloaded hosts", hosts.len());
+        Ok(Self { hosts })
+    }
+
+    pub fn is_preloaded(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        if self.hosts.contains_key(&host) {
+            return true;
+        }
+
+        // Walk up the parents, they only count when they include subdomains
+        let mut rest = host.as_str();
+        while let Some((_, parent)) = rest.split_once('.') {
+            if self.hosts.get(parent) == Some(&true) {
+                return true;
+            }
+            rest = parent;
+        }
+        false
+    }
+}
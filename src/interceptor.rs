@@ -0,0 +1,25 @@
+use async_trait::async_trait;
+use hyper::http::{request, response};
+use hyper::Response;
+use crate::types::ResponseBody;
+
+// What the proxy should do with a request after an interceptor has looked at it
+pub enum Action {
+    // Forward the (possibly rewritten) request upstream
+    Continue,
+    // Answer the client with this response without contacting the upstream
+    ShortCircuit(Response<ResponseBody>),
+    // Refuse the request with the configured blocked response
+    Block,
+}
+
+// Inspects and rewrites traffic for embedders using boring-proxy as a library.
+// Interceptors run in order, the first one that does not continue ends the chain.
+#[async_trait]
+pub trait Interceptor: Send + Sync {
+    async fn on_request(&self, _req: &mut request::Parts) -> Action {
+        Action::Continue
+    }
+
+    async fn on_response(&self, _res: &mut response::Parts) {}
+}
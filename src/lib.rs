@@ -0,0 +1,26 @@
+pub mod admin;
+pub mod auth;
+pub mod cache;
+pub mod cert_manager;
+pub mod config;
+pub mod cookie_jar;
+pub mod dedup;
+pub mod encoding;
+pub mod fingerprint;
+pub mod forwarded;
+pub mod hooks;
+pub mod host_filter;
+pub mod hsts;
+pub mod interceptor;
+pub mod logging;
+pub mod matcher;
+pub mod metrics;
+pub mod pac;
+pub mod session_manager;
+pub mod session_store;
+pub mod types;
+pub mod upstream_cert;
+pub mod util;
+pub mod websocket_handler;
+pub mod proxy;
+pub mod recorder;
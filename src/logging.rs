@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use parking_lot::Mutex;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+use crate::config::{Cli, LogFormat, LogLevel};
+use crate::types::Error;
+
+// Short names accepted in log_filters, mapped to the module that logs for them
+const COMPONENT_ALIASES: &[(&str, &str)] = &[
+    ("admin", "admin"),
+    ("auth", "auth"),
+    ("cache", "cache"),
+    ("cert", "cert_manager"),
+    ("har", "recorder"),
+    ("metrics", "metrics"),
+    ("pac", "pac"),
+    ("proxy", "proxy"),
+    ("session", "session_manager"),
+    ("tls", "upstream_cert"),
+    ("ws", "websocket_handler"),
+];
+
+// Resolve an alias or bare module name to a full target, anything else (e.g. "hyper") is used as is
+fn log_target(name: &str) -> String {
+    COMPONENT_ALIASES.iter()
+        .find(|(alias, module)| *alias == name || *module == name)
+        .map(|(_, module)| format!("boring_proxy::{}", module))
+        .unwrap_or_else(|| name.to_string())
+}
+
+// Handle for changing per-component log levels while the proxy runs
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default_filter: String,
+    filters: Mutex<HashMap<String, LogLevel>>,
+}
+
+impl LogControl {
+    fn build_filter(default_filter: &str, filters: &HashMap<String, LogLevel>) -> Result<EnvFilter, Error> {
+        let mut directives = vec![default_filter.to_string()];
+        directives.extend(filters.iter().map(|(name, level)| format!("{}={}", log_target(name), level.as_str())));
+        EnvFilter::try_new(directives.join(","))
+            .map_err(|e| format!("Invalid log filter {}: {}", directives.join(","), e).into())
+    }
+
+    // Merge these levels into the current ones and apply them
+    pub fn set_filters(&self, filters: &HashMap<String, LogLevel>) -> Result<(), Error> {
+        let mut current = self.filters.lock();
+        let mut updated = current.clone();
+        updated.extend(filters.iter().map(|(name, level)| (name.clone(), *level)));
+
+        let filter = Self::build_filter(&self.default_filter, &updated)?;
+        self.handle.reload(filter)
+            .map_err(|e| format!("Failed to apply log filter: {}", e))?;
+        *current = updated;
+        Ok(())
+    }
+}
+
+// Install the global subscriber, per-component levels from the config are applied later through set_filters
+pub fn init(cli: &Cli) -> Result<LogControl, Error> {
+    // RUST_LOG is still honoured when neither the flag nor BORING_PROXY_LOG is set
+    let log_level = cli.log_level.clone()
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| "info".to_string());
+    let filter = EnvFilter::try_new(&log_level)
+        .map_err(|e| format!("Invalid log level {}: {}", log_level, e))?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry().with(filter);
+
+    match cli.log_format {
+        LogFormat::Text => registry.with(fmt::layer()).init(),
+        LogFormat::Json => registry.with(fmt::layer().json().flatten_event(true)).init(),
+    }
+
+    Ok(LogControl {
+        handle,
+        default_filter: log_level,
+        filters: Mutex::new(HashMap::new()),
+    })
+}
@@ -1,78 +1,186 @@
-mod cert_manager;
-mod session_manager;
-mod types;
-mod websocket_handler;
-mod proxy;
-
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use hyper::{service::service_fn};
-use hyper_util::rt::TokioIo;
+use tokio::sync::watch;
 use std::time::Duration;
-use types::{Error, log, full};
-use proxy::Proxy;
+use clap::Parser;
+use boring_proxy::{
+    admin,
+    auth::IpAllowlist,
+    config::{Cli, Config},
+    logging,
+    metrics,
+    pac,
+    proxy::Proxy,
+    types::Error,
+};
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let addr = "127.0.0.1:8888";
-    log("PROXY", &format!("Starting MITM proxy on http://{}", addr));
+    let cli = Cli::parse();
+    let log_control = Arc::new(logging::init(&cli)?);
+
+    let config = Config::load(&cli)?;
+    log_control.set_filters(&config.log_filters)?;
+    let addr = config.bind_addr;
+    info!(component = "PROXY", "Starting MITM proxy on http://{}", addr);
 
     // Initialize proxy
-    let proxy = Arc::new(Proxy::new().await?);
+    let proxy = Arc::new(Proxy::new(&config, Vec::new()).await?);
+    proxy.cert_manager().check_and_renew_ca()?;
     
     // Print CA certificate for installation if needed
     let ca_cert = proxy.get_ca_cert_pem()?;
-    log("CERT", "CA Certificate (install this in your browser if not already installed):");
+    info!(component = "CERT", "CA Certificate (install this in your browser if not already installed):");
     println!("{}", ca_cert);
 
     // Start listening
-    let listener = TcpListener::bind(addr).await?;
-    log("PROXY", &format!("Server listening on {}", addr));
-    log("PROXY", "Waiting for connections...");
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    info!(component = "PROXY", "Server listening on {}", addr);
+    info!(component = "PROXY", "Waiting for connections...");
+
+    let allowlist = IpAllowlist::new(config.allow_ips.clone());
+
+    // Serve Prometheus metrics on their own listener when enabled
+    if let Some(metrics_addr) = config.metrics_addr {
+        let proxy_clone = Arc::clone(&proxy);
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics_addr, proxy_clone).await {
+                error!(component = "METRICS", "Metrics server failed: {}", e);
+            }
+        });
+    }
+
+    // Serve the PAC file so browsers only need its URL
+    if let Some(pac_addr) = config.pac_addr {
+        let bypass = config.pac_bypass.clone();
+        tokio::spawn(async move {
+            if let Err(e) = pac::serve(pac_addr, addr, bypass).await {
+                error!(component = "PAC", "PAC server failed: {}", e);
+            }
+        });
+    }
 
     // Spawn session cleanup task
     let proxy_clone = Arc::clone(&proxy);
+    let cleanup_interval = Duration::from_secs(config.cleanup_interval_secs);
     tokio::spawn(async move {
         loop {
-            tokio::time::sleep(Duration::from_secs(300)).await; // Clean up every 5 minutes
-            proxy_clone.session_manager().cleanup_sessions();
+            tokio::time::sleep(cleanup_interval).await;
+            proxy_clone.session_manager().cleanup_sessions().await;
         }
     });
 
-    loop {
-        let (stream, addr) = listener.accept().await?;
-        log("CONN", &format!("New connection from: {}", addr));
+    // Check the CA expiry once a day, a renewed CA has to be installed again
+    let proxy_clone = Arc::clone(&proxy);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(24 * 60 * 60)).await;
+            if let Err(e) = proxy_clone.cert_manager().check_and_renew_ca() {
+                error!(component = "CERT", "CA renewal failed: {}", e);
+            }
+        }
+    });
 
-        let proxy = Arc::clone(&proxy);
+    // Periodically save sessions so a crash loses at most one interval
+    if let Some(session_file) = config.session_file.clone() {
+        let proxy_clone = Arc::clone(&proxy);
+        let save_interval = Duration::from_secs(config.session_save_interval_secs);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(save_interval).await;
+                if let Err(e) = proxy_clone.session_manager().save(&session_file) {
+                    error!(component = "SESSION", "Session save failed: {}", e);
+                }
+            }
+        });
+    }
 
+    // Reload the allow and block lists on SIGHUP
+    #[cfg(unix)]
+    if config.allowlist.is_some() || config.blocklist.is_some() {
+        let proxy_clone = Arc::clone(&proxy);
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
         tokio::spawn(async move {
-            let io = TokioIo::new(stream);
-
-            let service = service_fn(move |req| {
-                let proxy = proxy.clone();
-                async move { 
-                    match proxy.handle_request(req).await {
-                        Ok(res) => Ok::<_, std::convert::Infallible>(res),
-                        Err(e) => {
-                            eprintln!("[ERROR] Request failed: {}", e);
-                            Ok(hyper::Response::builder()
-                                .status(500)
-                                .body(full(format!("Error: {}", e)))
-                                .unwrap())
-                        }
-                    }
+            while hangup.recv().await.is_some() {
+                if let Err(e) = proxy_clone.reload_host_filter() {
+                    error!(component = "BLOCK", "Host list reload failed: {}", e);
                 }
-            });
-
-            if let Err(err) = hyper::server::conn::http1::Builder::new()
-                .preserve_header_case(true)
-                .title_case_headers(true)
-                .serve_connection(io, service)
-                .with_upgrades()
-                .await
-            {
-                eprintln!("[ERROR] Connection failed: {}", err);
             }
         });
     }
+
+    // Stop on Ctrl-C, SIGTERM or when the admin API asks for it
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    let ctrl_c_tx = shutdown_tx.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = ctrl_c_tx.send(true);
+        }
+    });
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        let terminate_tx = shutdown_tx.clone();
+        tokio::spawn(async move {
+            if terminate.recv().await.is_some() {
+                let _ = terminate_tx.send(true);
+            }
+        });
+    }
+
+    // Serve the admin API against the same proxy instance when enabled
+    if let Some(admin_addr) = config.admin_addr {
+        let proxy_clone = Arc::clone(&proxy);
+        let compress_threshold = config.admin_compress_threshold_bytes;
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(admin_addr, proxy_clone, log_control, shutdown_tx, compress_threshold).await {
+                error!(component = "ADMIN", "Admin server failed: {}", e);
+            }
+        });
+    }
+
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown_rx.changed() => break,
+        };
+        if !allowlist.allows(addr.ip()) {
+            // Dropping the stream closes the socket before anything is read
+            warn!(component = "CONN", "Rejected connection from {}", addr.ip());
+            continue;
+        }
+        info!(component = "CONN", "New connection from: {}", addr);
+
+        // Probe idle clients so a tunnel whose client vanished errors out and frees its task
+        if config.downstream_keepalive_secs > 0 {
+            let idle = Duration::from_secs(config.downstream_keepalive_secs);
+            let keepalive = socket2::TcpKeepalive::new().with_time(idle).with_interval(idle);
+            if let Err(e) = socket2::SockRef::from(&stream).set_tcp_keepalive(&keepalive) {
+                warn!(component = "CONN", "Failed to enable keepalive for {}: {}", addr, e);
+            }
+        }
+
+        proxy.serve_connection(stream, addr);
+    }
+
+    // Stop accepting and let in-flight requests finish, then write the HAR recording and persisted
+    // cookies before exiting. Connections still open after the drain timeout are dropped.
+    info!(component = "PROXY", "Shutting down");
+    drop(listener);
+    proxy.drain(Duration::from_secs(config.drain_timeout_secs)).await;
+    if let (Some(recorder), Some(har_output)) = (proxy.recorder(), &config.har_output) {
+        if let Err(e) = recorder.export(har_output) {
+            error!(component = "HAR", "HAR export failed: {}", e);
+        }
+    }
+    proxy.session_manager().save_cookies();
+    if let Some(session_file) = &config.session_file {
+        if let Err(e) = proxy.session_manager().save(session_file) {
+            error!(component = "SESSION", "Session save failed: {}", e);
+        }
+    }
+
+    Ok(())
 }
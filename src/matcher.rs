@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use crate::types::Error;
+
+// Exact hostnames and glob patterns such as *.ads.example.com or tracker.*.net, compiled once at startup.
+// Exact names are looked up first, the globs are only tried when that misses. Globs are split into
+// labels and matched label by label, so * never spans a dot.
+#[derive(Default)]
+pub struct DomainMatcher {
+    hosts: HashSet<String>,
+    patterns: Vec<Vec<glob::Pattern>>,
+}
+
+impl DomainMatcher {
+    // Blank entries and # comments are skipped, source names where the entries came from in errors
+    pub fn new<'a>(entries: impl IntoIterator<Item = &'a str>, source: &str) -> Result<Self, Error> {
+        let mut hosts = HashSet::new();
+        let mut patterns = Vec::new();
+        for entry in entries {
+            let entry = entry.trim().to_lowercase();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+
+            if entry.contains(['*', '?', '[']) {
+                let labels = entry.split('.')
+                    .map(glob::Pattern::new)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("Invalid host pattern {} in {}: {}", entry, source, e))?;
+                patterns.push(labels);
+            } else {
+                hosts.insert(entry);
+            }
+        }
+        Ok(Self { hosts, patterns })
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        if self.hosts.contains(&host) {
+            return true;
+        }
+
+        let labels: Vec<&str> = host.split('.').collect();
+        self.patterns.iter().any(|pattern| {
+            pattern.len() == labels.len() && pattern.iter().zip(&labels).all(|(glob, label)| glob.matches(label))
+        })
+    }
+
+    pub fn host_count(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(entries: &[&str]) -> DomainMatcher {
+        DomainMatcher::new(entries.iter().copied(), "test").unwrap()
+    }
+
+    #[test]
+    fn exact_host() {
+        let matcher = matcher(&["ads.example.com"]);
+        assert!(matcher.matches("ads.example.com"));
+        assert!(matcher.matches("ADS.example.com."));
+        assert!(!matcher.matches("www.ads.example.com"));
+    }
+
+    #[test]
+    fn wildcard_matches_one_label() {
+        let matcher = matcher(&["*.example.com"]);
+        assert!(matcher.matches("ads.example.com"));
+        assert!(!matcher.matches("a.b.example.com"));
+        assert!(!matcher.matches("example.com"));
+    }
+
+    #[test]
+    fn wildcard_in_several_labels() {
+        let matcher = matcher(&["*.*.net"]);
+        assert!(matcher.matches("tracker.cdn.net"));
+        assert!(!matcher.matches("cdn.net"));
+        assert!(!matcher.matches("a.tracker.cdn.net"));
+    }
+
+    #[test]
+    fn no_match() {
+        let matcher = matcher(&["# comment", "", "tracker.*.net", "ads.example.com"]);
+        assert!(!matcher.matches("example.org"));
+        assert!(!matcher.matches("tracker.net"));
+        assert!(!matcher.matches("tracker.example.org"));
+        assert_eq!((matcher.host_count(), matcher.pattern_count()), (1, 1));
+    }
+}
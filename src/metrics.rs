@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::{body::Incoming, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use tokio::net::TcpListener;
+use crate::proxy::Proxy;
+use crate::types::{Error, ResponseResult, full};
+use tracing::{error, info};
+
+// Prometheus metrics for the proxy, served in text format on the metrics listener
+pub struct MetricsRegistry {
+    registry: Registry,
+    requests_total: IntCounterVec,
+    request_duration: HistogramVec,
+    errors_total: IntCounter,
+    active_sessions: IntGauge,
+    bytes_total: IntCounterVec,
+    websockets_open: IntGauge,
+    cert_cache_hits: IntCounter,
+    cert_cache_misses: IntCounter,
+    cert_cache_entries: IntGauge,
+    cert_cache_bytes: IntGauge,
+    cert_cache_evictions: IntCounterVec,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Result<Self, Error> {
+        let registry = Registry::new();
+
+        let requests_total = IntCounterVec::new(
+            Opts::new("proxy_requests_total", "Requests forwarded upstream"),
+            &["host", "method", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("proxy_request_duration_seconds", "Time until the upstream response head was received"),
+            &["host"],
+        )?;
+        let errors_total = IntCounter::new("proxy_errors_total", "Requests that failed with an error")?;
+        let active_sessions = IntGauge::new("proxy_active_sessions", "Sessions currently held by the session manager")?;
+        let bytes_total = IntCounterVec::new(
+            Opts::new("proxy_bytes_total", "Body bytes sent to and received from upstreams"),
+            &["host", "direction"],
+        )?;
+        let websockets_open = IntGauge::new("proxy_websockets_open", "WebSocket connections currently relayed")?;
+        let cert_cache_hits = IntCounter::new("cert_cache_hits_total", "Leaf certificates served from the cache")?;
+        let cert_cache_misses = IntCounter::new("cert_cache_misses_total", "Leaf certificates that had to be generated")?;
+        let cert_cache_entries = IntGauge::new("cert_cache_entries", "Leaf certificates currently cached")?;
+        let cert_cache_bytes = IntGauge::new("cert_cache_bytes", "DER bytes of the cached leaf chains and keys")?;
+        let cert_cache_evictions = IntCounterVec::new(
+            Opts::new("cert_cache_evictions_total", "Leaf certificates removed from the cache"),
+            &["reason"],
+        )?;
+
+        registry.register(Box::new(requests_total.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(errors_total.clone()))?;
+        registry.register(Box::new(active_sessions.clone()))?;
+        registry.register(Box::new(bytes_total.clone()))?;
+        registry.register(Box::new(websockets_open.clone()))?;
+        registry.register(Box::new(cert_cache_hits.clone()))?;
+        registry.register(Box::new(cert_cache_misses.clone()))?;
+        registry.register(Box::new(cert_cache_entries.clone()))?;
+        registry.register(Box::new(cert_cache_bytes.clone()))?;
+        registry.register(Box::new(cert_cache_evictions.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests_total,
+            request_duration,
+            errors_total,
+            active_sessions,
+            bytes_total,
+            websockets_open,
+            cert_cache_hits,
+            cert_cache_misses,
+            cert_cache_entries,
+            cert_cache_bytes,
+            cert_cache_evictions,
+        })
+    }
+
+    // A missing status means the request failed before a response was produced
+    pub fn observe_request(&self, host: &str, method: &str, status: Option<StatusCode>, duration: Duration) {
+        match status {
+            Some(status) => self.requests_total.with_label_values(&[host, method, status.as_str()]).inc(),
+            None => {
+                self.requests_total.with_label_values(&[host, method, "error"]).inc();
+                self.errors_total.inc();
+            }
+        }
+        self.request_duration.with_label_values(&[host]).observe(duration.as_secs_f64());
+    }
+
+    pub fn add_bytes_sent(&self, host: &str, bytes: usize) {
+        self.bytes_total.with_label_values(&[host, "sent"]).inc_by(bytes as u64);
+    }
+
+    pub fn add_bytes_received(&self, host: &str, bytes: usize) {
+        self.bytes_total.with_label_values(&[host, "received"]).inc_by(bytes as u64);
+    }
+
+    pub fn websocket_opened(&self) {
+        self.websockets_open.inc();
+    }
+
+    pub fn websocket_closed(&self) {
+        self.websockets_open.dec();
+    }
+
+    pub fn cert_cache_hit(&self) {
+        self.cert_cache_hits.inc();
+    }
+
+    pub fn cert_cache_miss(&self) {
+        self.cert_cache_misses.inc();
+    }
+
+    pub fn set_active_sessions(&self, count: usize) {
+        self.active_sessions.set(count as i64);
+    }
+
+    // bytes is the cache weight of the certificate, so the gauges track what the cache holds
+    pub fn cert_cached(&self, bytes: u32) {
+        self.cert_cache_entries.inc();
+        self.cert_cache_bytes.add(i64::from(bytes));
+    }
+
+    // reason is expired, replaced, size or explicit
+    pub fn cert_cache_evicted(&self, reason: &str, bytes: u32) {
+        self.cert_cache_evictions.with_label_values(&[reason]).inc();
+        self.cert_cache_entries.dec();
+        self.cert_cache_bytes.sub(i64::from(bytes));
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+async fn handle_metrics_request(proxy: Arc<Proxy>, req: Request<Incoming>) -> ResponseResult {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full("Not found"))?);
+    }
+
+    // The session count is only needed at scrape time, so read it here rather than tracking every change
+    let metrics = proxy.metrics();
+    metrics.set_active_sessions(proxy.session_manager().sessions().read().len());
+
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, TextEncoder::new().format_type())
+        .body(full(metrics.encode()?))?)
+}
+
+// Serve GET /metrics on its own listener
+pub async fn serve(addr: SocketAddr, proxy: Arc<Proxy>) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("Failed to bind metrics listener {}: {}", addr, e))?;
+    info!(component = "METRICS", "Serving metrics on http://{}/metrics", addr);
+    serve_listener(listener, proxy).await
+}
+
+// Serve GET /metrics on an already bound listener
+pub async fn serve_listener(listener: TcpListener, proxy: Arc<Proxy>) -> Result<(), Error> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let proxy = Arc::clone(&proxy);
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let proxy = Arc::clone(&proxy);
+                async move {
+                    match handle_metrics_request(proxy, req).await {
+                        Ok(res) => Ok::<_, std::convert::Infallible>(res),
+                        Err(e) => {
+                            error!(component = "METRICS", "Metrics request failed: {}", e);
+                            Ok(Response::builder()
+                                .status(500)
+                                .body(full(format!("Error: {}", e)))
+                                .unwrap())
+                        }
+                    }
+                }
+            });
+
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                error!(component = "METRICS", "Metrics connection failed: {}", e);
+            }
+        });
+    }
+}
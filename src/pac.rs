@@ -0,0 +1,100 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use hyper::{body::Incoming, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use crate::types::{Error, ResponseResult, full};
+use tracing::{error, info};
+
+const PAC_CONTENT_TYPE: &str = "application/x-ns-proxy-autoconfig";
+
+// Proxy auto-config script sending everything through the proxy except loopback and the bypass hosts.
+// Bypass entries are exact hosts, IP literals or shell globs like *.example.com.
+fn pac_script(proxy: &str, bypass: &[String]) -> String {
+    let bypass = bypass.iter()
+        .map(|host| host.trim().trim_start_matches('[').trim_end_matches(']').to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect::<Vec<_>>();
+
+    format!(r#"function FindProxyForURL(url, host) {{
+    // IPv6 literals may arrive in brackets
+    host = host.toLowerCase().replace(/^\[(.*)\]$/, "$1");
+    if (host === "localhost" || isPlainHostName(host) || shExpMatch(host, "127.*") || host === "::1") {{
+        return "DIRECT";
+    }}
+    var isIp = /^[0-9.]+$/.test(host) || host.indexOf(":") !== -1;
+    var bypass = {};
+    for (var i = 0; i < bypass.length; i++) {{
+        // Globs only apply to names, an IP literal has to be listed exactly
+        if (host === bypass[i] || (!isIp && shExpMatch(host, bypass[i]))) {{
+            return "DIRECT";
+        }}
+    }}
+    return "PROXY {}";
+}}
+"#, serde_json::to_string(&bypass).unwrap_or_else(|_| "[]".to_string()), proxy)
+}
+
+fn handle_pac_request(proxy_addr: SocketAddr, bypass: &[String], req: Request<Incoming>) -> ResponseResult {
+    if req.method() != Method::GET {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(full("Method not allowed"))?);
+    }
+
+    // A proxy bound to every interface is reached on the same host the browser fetched the PAC file from
+    let proxy = if proxy_addr.ip().is_unspecified() {
+        let host = req.headers().get(hyper::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<hyper::http::uri::Authority>().ok())
+            .map(|authority| authority.host().to_string());
+        match host {
+            Some(host) => format!("{}:{}", host, proxy_addr.port()),
+            None => format!("127.0.0.1:{}", proxy_addr.port()),
+        }
+    } else {
+        proxy_addr.to_string()
+    };
+
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, PAC_CONTENT_TYPE)
+        .body(full(pac_script(&proxy, bypass)))?)
+}
+
+// Serve the PAC file for any GET on its own listener
+pub async fn serve(addr: SocketAddr, proxy_addr: SocketAddr, bypass: Vec<String>) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("Failed to bind PAC listener {}: {}", addr, e))?;
+    info!(component = "PAC", "Serving proxy auto-config on http://{}/proxy.pac", addr);
+
+    let bypass = Arc::new(bypass);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let bypass = Arc::clone(&bypass);
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let result = handle_pac_request(proxy_addr, &bypass, req);
+                async move {
+                    match result {
+                        Ok(res) => Ok::<_, std::convert::Infallible>(res),
+                        Err(e) => {
+                            error!(component = "PAC", "PAC request failed: {}", e);
+                            Ok(Response::builder()
+                                .status(500)
+                                .body(full(format!("Error: {}", e)))
+                                .unwrap())
+                        }
+                    }
+                }
+            });
+
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                error!(component = "PAC", "PAC connection failed: {}", e);
+            }
+        });
+    }
+}
@@ -1,44 +1,244 @@
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
 use hyper::{
-    body::Incoming,
-    Method, Request, Response,
+    body::{Body, Incoming},
+    http::response::Parts as ResponseParts,
+    Method, Request, Response, StatusCode,
 };
 use hyper_util::rt::TokioIo;
 use http_body_util::BodyExt;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures_util::{StreamExt, TryStreamExt};
+use parking_lot::RwLock;
+use secrecy::ExposeSecret;
 use tokio_rustls::rustls::ServerConfig;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use crate::{
+    auth::{ProxyAuth, proxy_auth_required},
+    cache::ResponseCache,
+    dedup::{self, Inflight, InflightRequests, SharedResponse},
+    fingerprint::{self, Ja3Fingerprint, Rewind},
     cert_manager::CertManager,
-    session_manager::SessionManager,
-    types::{Error, ResponseResult, log, empty, full},
-    websocket_handler::{handle_websocket_upgrade, create_websocket_response},
+    config::{Config, RetryPolicy},
+    forwarded::{self, ExistingForwarded, ForwardedHeaderMode, ForwardedHop},
+    hooks::{HeaderInjectHook, RecompressHook, RequestHook, ResponseHook, WsHook, WsTextRewriteHook},
+    host_filter::{HostFilter, PassthroughList},
+    hsts::HstsPreload,
+    interceptor::{Action, Interceptor},
+    metrics::MetricsRegistry,
+    recorder::{Exchange, HarRecorder},
+    session_manager::{RequestRecord, SessionConfig, SessionManager},
+    session_store::RedisSessionStore,
+    types::{Error, ResponseBody, ResponseResult, classify_error, client_hints, empty, error_response, full, streaming, to_rquest_method},
+    upstream_cert::UpstreamCertLog,
+    util::ResponseExt,
+    websocket_handler::{connect_websocket_upstream, handle_websocket_upgrade, create_websocket_response, WsLogger},
 };
-use rquest::{Method as RqMethod, Client as RqClient};
+use rquest::{Method as RqMethod, Client as RqClient, Impersonate};
+use tracing::{debug, error, info, warn};
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+// Largest response shared with deduplicated requests when max_response_buffer_bytes is unset
+const DEDUP_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+// Plain HTTP requests to this host are answered by the proxy itself, e.g. http://boring.proxy/ca.crt
+const CA_HOST: &str = "boring.proxy";
+const CA_CONTENT_TYPE: &str = "application/x-x509-ca-cert";
+
+const H2C_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Whether the client opened with the HTTP/2 connection preface. The bytes are only peeked, so hyper still
+// reads them.
+async fn starts_with_h2c_preface(stream: &tokio::net::TcpStream) -> std::io::Result<bool> {
+    let mut buf = [0u8; H2C_PREFACE.len()];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 || !H2C_PREFACE.starts_with(&buf[..n]) {
+            return Ok(false);
+        }
+        if n == H2C_PREFACE.len() {
+            return Ok(true);
+        }
+        // peek returns at once while the partial preface sits in the buffer, so wait for the rest
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// Upgrade protocol a request asks for, unless it is a WebSocket upgrade which is relayed frame by frame
+fn raw_upgrade_protocol(req: &Request<Incoming>) -> Option<String> {
+    req.headers().get(hyper::header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|protocol| !protocol.eq_ignore_ascii_case("websocket"))
+        .map(str::to_string)
+}
+
+fn is_grpc(headers: &hyper::HeaderMap) -> bool {
+    headers.get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/grpc"))
+}
+
+// Server-sent events never end on their own, so they can't be buffered
+fn is_event_stream(headers: &hyper::HeaderMap) -> bool {
+    headers.get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+// Collect a body of at most `limit` bytes. Past the limit the bytes read so far and the rest of the
+// body come back as one streaming body instead.
+async fn collect_up_to(mut body: ResponseBody, limit: usize) -> Result<Result<Bytes, ResponseBody>, Error> {
+    let mut buffered = BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        let Ok(data) = frame?.into_data() else {
+            continue;
+        };
+        buffered.extend_from_slice(&data);
+        if buffered.len() > limit {
+            let head = futures_util::stream::once(async move { Ok::<_, Error>(buffered.freeze()) });
+            return Ok(Err(streaming(head.chain(body.into_data_stream()))));
+        }
+    }
+    Ok(Ok(buffered.freeze()))
+}
+
+// Service and method of a gRPC call from its /package.Service/Method path
+fn grpc_call(path: &str) -> Option<(&str, &str)> {
+    path.strip_prefix('/')?.split_once('/')
+        .filter(|(service, method)| !service.is_empty() && !method.is_empty())
+}
 
 pub struct Proxy {
     cert_manager: Arc<CertManager>,
     session_manager: Arc<SessionManager>,
+    response_cache: Option<ResponseCache>,
+    cache_head_to_get: bool,
+    inflight: Option<InflightRequests>,
+    max_request_headers: usize,
+    h2c: bool,
+    max_response_buffer: Option<usize>,
+    sse_timeout: Option<Duration>,
+    recorder: Option<HarRecorder>,
+    host_filter: Option<RwLock<HostFilter>>,
+    passthrough: PassthroughList,
+    passthrough_headers: Vec<hyper::header::HeaderName>,
+    blocked_status: StatusCode,
+    blocked_message: String,
+    hsts_preload: Option<HstsPreload>,
+    retry_policy: RetryPolicy,
+    forwarded_header: ForwardedHeaderMode,
+    forwarded_existing: ExistingForwarded,
+    proxy_ip: IpAddr,
+    proxy_auth: Option<ProxyAuth>,
+    request_hooks: Vec<Box<dyn RequestHook + Send + Sync>>,
+    response_hooks: Vec<Box<dyn ResponseHook + Send + Sync>>,
+    ws_hooks: Arc<Vec<Box<dyn WsHook + Send + Sync>>>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    metrics: Arc<MetricsRegistry>,
+    inbound_http2: bool,
+    upstream_certs: UpstreamCertLog,
+    shutdown: CancellationToken,
+    connections: TaskTracker,
+    websocket_tasks: TaskTracker,
+    ws_shutdown_grace: Duration,
+    ws_logger: Option<WsLogger>,
 }
 
 impl Proxy {
-    pub async fn new() -> Result<Self, Error> {
-        log("PROXY", "Creating new proxy instance...");
+    pub async fn new(config: &Config, interceptors: Vec<Arc<dyn Interceptor>>) -> Result<Self, Error> {
+        info!(component = "PROXY", "Creating new proxy instance...");
         
         // Initialize certificate manager
-        let cert_manager = Arc::new(CertManager::new()?);
-        let session_manager = Arc::new(SessionManager::new());
+        let metrics = Arc::new(MetricsRegistry::new()?);
+        let cert_manager = Arc::new(CertManager::new(config, Arc::clone(&metrics))?);
+        let session_config = SessionConfig::from_config(config)?;
+        let mut session_manager = match &config.session_file {
+            Some(path) if path.exists() => SessionManager::load(session_config, path)?,
+            _ => SessionManager::new(session_config),
+        };
+        if let Some(url) = &config.session_redis_url {
+            session_manager = session_manager.with_store(RedisSessionStore::connect(url.expose_secret()).await?);
+        }
+        let session_manager = Arc::new(session_manager);
+        let response_cache = config.cache_enabled.then(ResponseCache::new);
+        let recorder = config.har_output.as_ref().map(|_| HarRecorder::new());
+        let host_filter = if config.allowlist.is_some() || config.blocklist.is_some() {
+            Some(RwLock::new(HostFilter::load(config.allowlist.as_deref(), config.blocklist.as_deref())?))
+        } else {
+            None
+        };
+        let mut response_hooks = config.inject_response_headers.iter()
+            .map(|(name, value)| HeaderInjectHook::new(name, value)
+                .map(|hook| Box::new(hook) as Box<dyn ResponseHook + Send + Sync>))
+            .collect::<Result<Vec<_>, _>>()?;
+        for rule in &config.recompress {
+            response_hooks.push(Box::new(RecompressHook::new(&rule.content_type_pattern, rule.encoding)?));
+        }
+        let ws_hooks = config.ws_text_rewrites.iter()
+            .map(|(pattern, replacement)| WsTextRewriteHook::new(pattern, replacement)
+                .map(|hook| Box::new(hook) as Box<dyn WsHook + Send + Sync>))
+            .collect::<Result<Vec<_>, _>>()?;
+        let hsts_preload = if config.hsts_preload_check {
+            Some(HstsPreload::load(config.hsts_preload_file.as_deref()).await?)
+        } else {
+            None
+        };
+        if config.rquest.decompress {
+            info!(component = "PROXY", "Decompressing upstream responses, Content-Encoding is removed");
+        } else {
+            info!(component = "PROXY", "Passing upstream responses through with their Content-Encoding");
+        }
+        let blocked_status = StatusCode::from_u16(config.blocked_status)
+            .map_err(|_| format!("Invalid blocked_status: {}", config.blocked_status))?;
 
-        log("PROXY", "Initialized proxy instance");
+        info!(component = "PROXY", "Initialized proxy instance");
 
         Ok(Self {
             cert_manager,
             session_manager,
+            response_cache,
+            cache_head_to_get: config.cache_head_to_get,
+            inflight: config.dedup.then(InflightRequests::new),
+            max_request_headers: config.max_request_headers,
+            h2c: config.h2c,
+            max_response_buffer: config.max_response_buffer_bytes,
+            sse_timeout: config.sse_timeout_secs.map(Duration::from_secs),
+            recorder,
+            host_filter,
+            passthrough: PassthroughList::new(&config.passthrough_hosts)?,
+            passthrough_headers: config.passthrough_headers.iter()
+                .map(|name| hyper::header::HeaderName::from_bytes(name.trim().as_bytes())
+                    .map_err(|_| format!("Invalid passthrough header: {}", name).into()))
+                .collect::<Result<Vec<_>, Error>>()?,
+            blocked_status,
+            blocked_message: config.blocked_message.clone(),
+            hsts_preload,
+            retry_policy: config.retry.clone(),
+            forwarded_header: config.forwarded_header,
+            forwarded_existing: config.forwarded_existing,
+            proxy_ip: config.bind_addr.ip(),
+            proxy_auth: config.proxy_auth.clone(),
+            request_hooks: Vec::new(),
+            response_hooks,
+            ws_hooks: Arc::new(ws_hooks),
+            interceptors,
+            metrics,
+            inbound_http2: config.inbound_http2,
+            upstream_certs: UpstreamCertLog::new(),
+            shutdown: CancellationToken::new(),
+            connections: TaskTracker::new(),
+            websocket_tasks: TaskTracker::new(),
+            ws_shutdown_grace: Duration::from_secs(config.ws_shutdown_grace_secs),
+            ws_logger: config.log_ws_frames.then_some(WsLogger),
         })
     }
 
-    pub fn create_server_config(&self, host: &str) -> Result<ServerConfig, Error> {
+    pub async fn create_server_config(&self, host: &str) -> Result<ServerConfig, Error> {
         // Get or create certificate
-        let (cert_chain, key) = self.cert_manager.get_or_create_cert(host)?;
+        let (cert_chain, key) = self.cert_manager.get_or_create_cert(host).await?;
 
         // Create TLS config
         let mut config = ServerConfig::builder()
@@ -46,8 +246,13 @@ impl Proxy {
             .with_no_client_auth()
             .with_single_cert(cert_chain, key)?;
 
-        // Only enable HTTP/1.1 to avoid WebSocket issues with HTTP/2
-        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        // HTTP/1.1 only by default since WebSockets need it. With h2 enabled, browsers still open their
+        // WebSocket connections offering only http/1.1 because extended CONNECT is not advertised.
+        config.alpn_protocols = if self.inbound_http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
 
         Ok(config)
     }
@@ -56,6 +261,157 @@ impl Proxy {
         self.cert_manager.get_ca_cert_pem()
     }
 
+    // The CA certificate for installing on devices that can't copy it from the startup output,
+    // DER at /ca.crt and PEM at /ca.pem
+    fn ca_cert_response(&self, path: &str) -> ResponseResult {
+        let body = match path {
+            "/ca.crt" | "/ca.der" => Bytes::from(self.cert_manager.get_ca_cert_der()?),
+            "/ca.pem" => Bytes::from(self.cert_manager.get_ca_cert_pem()?),
+            _ => return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(full("Download the CA certificate from /ca.crt (DER) or /ca.pem (PEM)"))?),
+        };
+        Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, CA_CONTENT_TYPE)
+            .header(hyper::header::CONTENT_DISPOSITION, format!("attachment; filename=\"boring-proxy{}\"", path.trim_start_matches("/ca")))
+            .body(full(body))?)
+    }
+
+    fn is_blocked(&self, host: &str) -> bool {
+        self.host_filter.as_ref().is_some_and(|filter| filter.read().is_blocked(host))
+    }
+
+    fn blocked_response(&self, host: &str) -> ResponseResult {
+        info!(component = "BLOCK", "Blocked request to {}", host);
+        Ok(Response::builder()
+            .status(self.blocked_status)
+            .header(hyper::header::CONTENT_TYPE, "text/plain")
+            .body(full(self.blocked_message.clone()))?)
+    }
+
+    // Re-read the allow and block lists, keeping the current ones if the new files fail to load
+    pub fn reload_host_filter(&self) -> Result<(), Error> {
+        if let Some(filter) = &self.host_filter {
+            let reloaded = filter.read().reload()?;
+            *filter.write() = reloaded;
+        }
+        Ok(())
+    }
+
+    // Serve a connection accepted on the proxy listener as a task shutdown waits for. It speaks HTTP/1.1,
+    // or h2c when enabled and the client opens with the HTTP/2 preface.
+    pub fn serve_connection(self: &Arc<Self>, stream: tokio::net::TcpStream, client_addr: SocketAddr) {
+        let proxy = Arc::clone(self);
+        self.connections.spawn(async move {
+            let h2c = proxy.h2c && match starts_with_h2c_preface(&stream).await {
+                Ok(h2c) => h2c,
+                Err(e) => {
+                    error!(component = "CONN", "Failed to read from {}: {}", client_addr, e);
+                    return;
+                }
+            };
+            let io = TokioIo::new(stream);
+            let shutdown = proxy.shutdown.clone();
+            let max_request_headers = proxy.max_request_headers;
+
+            let service = hyper::service::service_fn(move |req| {
+                let proxy = Arc::clone(&proxy);
+                async move {
+                    match proxy.handle_request(req, client_addr).await {
+                        Ok(res) => Ok::<_, std::convert::Infallible>(res),
+                        Err(e) => {
+                            error!(component = "PROXY", "Request failed: {}", e);
+                            Ok(error_response(&e))
+                        }
+                    }
+                }
+            });
+
+            // On shutdown, finish the requests in flight and close instead of waiting for the next one
+            let result = if h2c {
+                debug!(component = "CONN", "Serving h2c to {}", client_addr);
+                let conn = hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
+                    .serve_connection(io, service);
+                tokio::pin!(conn);
+                tokio::select! {
+                    result = conn.as_mut() => result,
+                    _ = shutdown.cancelled() => {
+                        conn.as_mut().graceful_shutdown();
+                        conn.await
+                    }
+                }
+            } else {
+                let conn = hyper::server::conn::http1::Builder::new()
+                    .max_headers(max_request_headers)
+                    .preserve_header_case(true)
+                    .title_case_headers(true)
+                    .serve_connection(io, service)
+                    .with_upgrades();
+                tokio::pin!(conn);
+                tokio::select! {
+                    result = conn.as_mut() => result,
+                    _ = shutdown.cancelled() => {
+                        conn.as_mut().graceful_shutdown();
+                        conn.await
+                    }
+                }
+            };
+            if let Err(err) = result {
+                error!(component = "PROXY", "Connection failed: {}", err);
+            }
+        });
+    }
+
+    // Stop every connection and wait up to `timeout` for in-flight requests and WebSocket close handshakes.
+    // Returns false when connections were still open at the deadline.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.connections.close();
+        self.websocket_tasks.close();
+        if !self.connections.is_empty() {
+            info!(component = "CONN", "Draining {} connections", self.connections.len());
+        }
+        if !self.websocket_tasks.is_empty() {
+            info!(component = "WS", "Closing {} WebSocket sessions", self.websocket_tasks.len());
+        }
+        self.shutdown.cancel();
+
+        let drained = tokio::time::timeout(timeout, async {
+            tokio::join!(self.connections.wait(), self.websocket_tasks.wait());
+        }).await;
+        if drained.is_err() {
+            warn!(
+                component = "CONN",
+                "Drain timeout expired with {} connections and {} WebSockets still open",
+                self.connections.len(), self.websocket_tasks.len()
+            );
+        }
+        drained.is_ok()
+    }
+
+    // Run `hook` on every request after the configured ones, the request body is collected for it
+    pub fn with_request_hook(mut self, hook: impl RequestHook + Send + Sync + 'static) -> Self {
+        self.request_hooks.push(Box::new(hook));
+        self
+    }
+
+    // Run `hook` on every response after the configured ones
+    pub fn with_response_hook(mut self, hook: impl ResponseHook + Send + Sync + 'static) -> Self {
+        self.response_hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn recorder(&self) -> Option<&HarRecorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn cert_manager(&self) -> Arc<CertManager> {
+        Arc::clone(&self.cert_manager)
+    }
+
+    pub fn metrics(&self) -> &MetricsRegistry {
+        &self.metrics
+    }
+
     pub fn session_manager(&self) -> Arc<SessionManager> {
         Arc::clone(&self.session_manager)
     }
@@ -71,48 +427,604 @@ impl Proxy {
         let final_url = res.url().to_string();
 
         if final_url != url {
-            log("WS", &format!("Following WebSocket redirect: {} -> {}", url, final_url));
+            info!(component = "WS", "Following WebSocket redirect: {} -> {}", url, final_url);
         }
 
-        // Now proceed with WebSocket upgrade using the final URL
-        let headers = req.headers().clone();
+        // Now proceed with WebSocket upgrade using the final URL, connecting upstream
+        // before answering the client so a rejected upgrade can be passed back as is
+        let ws_res = connect_websocket_upstream(&client, &final_url, req.headers()).await?;
+        if ws_res.status() != StatusCode::SWITCHING_PROTOCOLS {
+            error!(component = "PROXY", "Upstream rejected WebSocket upgrade for {} with status {}", final_url, ws_res.status());
+            let res = ws_res.into_inner();
+            let mut builder = Response::builder()
+                .status(res.status());
+            for (k, v) in res.headers() {
+                builder = builder.header(k, v);
+            }
+            return Ok(builder.body(streaming(res.bytes_stream()))?);
+        }
+        let ws_server = ws_res.into_websocket().await?;
+
         let response = create_websocket_response()?;
         let upgrade = hyper::upgrade::on(req);
 
-        // Handle WebSocket connection in background task
-        tokio::spawn(async move {
+        // Handle WebSocket connection in background task, tracked so shutdown can wait for it
+        let shutdown = self.shutdown.clone();
+        let grace = self.ws_shutdown_grace;
+        let logger = self.ws_logger;
+        let hooks = Arc::clone(&self.ws_hooks);
+        let metrics = Arc::clone(&self.metrics);
+        self.websocket_tasks.spawn(async move {
             match upgrade.await {
                 Ok(upgraded) => {
                     let io = hyper_util::rt::TokioIo::new(upgraded);
-                    if let Err(e) = handle_websocket_upgrade(
-                        io,
-                        client,
-                        final_url,
-                        headers,
-                    ).await {
-                        eprintln!("[ERROR] WebSocket handling failed: {}", e);
+                    metrics.websocket_opened();
+                    if let Err(e) = handle_websocket_upgrade(io, ws_server, shutdown, grace, logger, hooks).await {
+                        error!(component = "WS", "WebSocket handling failed: {}", e);
                     }
+                    metrics.websocket_closed();
                 }
-                Err(e) => eprintln!("[ERROR] WebSocket upgrade failed: {}", e),
+                Err(e) => error!(component = "WS", "WebSocket upgrade failed: {}", e),
             }
         });
 
         Ok(response)
     }
 
+    // Protocols other than WebSocket (e.g. TLS/1.0 or MSRPC) are spliced as raw bytes once the upstream
+    // has answered 101, the proxy does not try to parse them
+    async fn handle_raw_upgrade(
+        &self,
+        req: Request<Incoming>,
+        client: RqClient,
+        url: String,
+        protocol: String,
+    ) -> ResponseResult {
+        info!(component = "PROXY", upgrade = %protocol, "Upgrade to {} requested for {}", protocol, url);
+
+        let method = to_rquest_method(req.method())
+            .ok_or_else(|| format!("Method {} is not supported", req.method()))?;
+        let mut rq = client.request(method, &url);
+        for (k, v) in req.headers() {
+            if k != hyper::header::HOST && k != hyper::header::PROXY_AUTHORIZATION {
+                rq = rq.header(k, v);
+            }
+        }
+        let res = rq.send().await?;
+
+        let mut builder = Response::builder()
+            .status(res.status());
+        for (k, v) in res.headers() {
+            builder = builder.header(k, v);
+        }
+        if res.status() != StatusCode::SWITCHING_PROTOCOLS {
+            info!(component = "PROXY", upgrade = %protocol, "Upstream declined the {} upgrade for {} with {}", protocol, url, res.status());
+            return Ok(builder.body(streaming(res.bytes_stream()))?);
+        }
+
+        let client_upgrade = hyper::upgrade::on(req);
+        tokio::spawn(async move {
+            let mut upstream = match res.upgrade().await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    error!(component = "PROXY", "Upstream {} upgrade failed for {}: {}", protocol, url, e);
+                    return;
+                }
+            };
+            let mut downstream = match client_upgrade.await {
+                Ok(upgraded) => TokioIo::new(upgraded),
+                Err(e) => {
+                    error!(component = "PROXY", "Client {} upgrade failed for {}: {}", protocol, url, e);
+                    return;
+                }
+            };
+
+            // The upstream socket belongs to this tunnel alone, so requests from other clients can never be
+            // pipelined onto it
+            match tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await {
+                Ok((sent, received)) => info!(
+                    component = "PROXY",
+                    upgrade = %protocol,
+                    "{} connection for {} closed after {} bytes sent and {} received", protocol, url, sent, received
+                ),
+                Err(e) => error!(component = "PROXY", upgrade = %protocol, "{} connection for {} failed: {}", protocol, url, e),
+            }
+        });
+
+        Ok(builder.body(empty())?)
+    }
+
+    // Splice the CONNECT tunnel straight to the origin, TLS stays end to end between client and origin.
+    // The 200 goes out first like for intercepted tunnels, a failed connect closes the client connection.
+    fn passthrough_tunnel(&self, req: Request<Incoming>, host: &str, port: u16) -> ResponseResult {
+        let target = format!("{}:{}", host, port);
+        debug!(component = "PROXY", "Passing through tunnel to {}", target);
+
+        let client_upgrade = hyper::upgrade::on(req);
+        tokio::spawn(async move {
+            let mut upstream = match tokio::net::TcpStream::connect(&target).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    error!(component = "PROXY", "Failed to connect passthrough tunnel to {}: {}", target, e);
+                    return;
+                }
+            };
+            let mut downstream = match client_upgrade.await {
+                Ok(upgraded) => TokioIo::new(upgraded),
+                Err(e) => {
+                    error!(component = "PROXY", "Connection upgrade failed for {}: {}", target, e);
+                    return;
+                }
+            };
+
+            match tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await {
+                Ok((sent, received)) => debug!(
+                    component = "PROXY",
+                    "Passthrough tunnel to {} closed after {} bytes sent and {} received", target, sent, received
+                ),
+                Err(e) => error!(component = "PROXY", "Passthrough tunnel to {} failed: {}", target, e),
+            }
+        });
+
+        Ok(Response::new(empty()))
+    }
+
+    // Send the request, retrying connect errors, timeouts and any configured statuses with exponential backoff.
+    // Safe methods are also retried when the connection broke mid-request, e.g. on a reset.
+    // The last attempt's result is returned as is.
+    async fn send_with_retry(&self, rq: rquest::RequestBuilder, url: &str, safe: bool) -> Result<rquest::Response, Error> {
+        let policy = &self.retry_policy;
+        if policy.safe_methods_only && !safe {
+            return Ok(rq.send().await?);
+        }
+
+        let mut delay = Duration::from_millis(policy.initial_delay_ms);
+        let mut attempt = 1;
+
+        loop {
+            let retry = match (attempt < policy.max_attempts).then(|| rq.try_clone()).flatten() {
+                Some(retry) => retry,
+                None => return Ok(rq.send().await?),
+            };
+
+            let reason = match retry.send().await {
+                Ok(res) if policy.retryable_statuses.contains(&res.status().as_u16()) => format!("status {}", res.status()),
+                Ok(res) => return Ok(res),
+                Err(e) if e.is_connect() || e.is_timeout() || (safe && e.is_request()) => e.to_string(),
+                Err(e) => return Err(e.into()),
+            };
+
+            warn!(component = "PROXY", "Attempt {} for {} failed ({}), retrying in {:?}", attempt, url, reason, delay);
+            tokio::time::sleep(delay).await;
+            delay = delay.mul_f64(policy.backoff_factor).min(MAX_RETRY_DELAY);
+            attempt += 1;
+        }
+    }
+
+    fn apply_response_hooks(&self, parts: &mut ResponseParts, body: &mut Bytes) -> Result<(), Error> {
+        let original_len = body.len();
+        for hook in &self.response_hooks {
+            hook.modify_response(parts, body)?;
+        }
+
+        // Keep Content-Length in line with a body the hooks changed
+        if body.len() != original_len {
+            parts.headers.insert(hyper::header::CONTENT_LENGTH, body.len().into());
+        }
+        Ok(())
+    }
+
+    async fn forward_request(
+        &self,
+        req: Request<Incoming>,
+        client: RqClient,
+        profile: Impersonate,
+        client_addr: SocketAddr,
+        host: &str,
+        url: String,
+    ) -> ResponseResult {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let result = match (&self.inflight, InflightRequests::key(&req)) {
+            (Some(inflight), Some(key)) => match inflight.join(key) {
+                Inflight::Follower(mut receiver) => {
+                    debug!(component = "PROXY", "Waiting for identical in-flight request to {}", url);
+                    dedup::follower_response(receiver.recv().await)
+                }
+                Inflight::Leader(guard) => {
+                    // The followers need the whole body, so it is buffered when it fits in the buffer limit.
+                    // Event streams, gRPC and larger bodies are streamed to the leader alone and the followers
+                    // are turned away rather than left waiting on a body that may never end.
+                    match self.intercept_and_send(req, client, profile, client_addr, host, url.clone()).await {
+                        Ok(res) => {
+                            let (parts, body) = res.into_parts();
+                            let limit = self.max_response_buffer.unwrap_or(DEDUP_MAX_BYTES);
+                            let too_large = parts.headers.get(hyper::header::CONTENT_LENGTH)
+                                .and_then(|v| v.to_str().ok())
+                                .and_then(|v| v.parse::<usize>().ok())
+                                .is_some_and(|len| len > limit);
+                            let shared = if too_large || is_event_stream(&parts.headers) || is_grpc(&parts.headers) {
+                                Err(body)
+                            } else {
+                                collect_up_to(body, limit).await?
+                            };
+
+                            match shared {
+                                Ok(body) => {
+                                    guard.finish(Ok(SharedResponse {
+                                        status: parts.status,
+                                        headers: parts.headers.clone(),
+                                        body: body.clone(),
+                                    }));
+                                    Ok(Response::from_parts(parts, full(body)))
+                                }
+                                Err(body) => {
+                                    debug!(component = "PROXY", "Not sharing the streamed response for {} with identical requests", url);
+                                    guard.finish(Err((StatusCode::SERVICE_UNAVAILABLE, "Identical request is being streamed, retry")));
+                                    Ok(Response::from_parts(parts, body))
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            guard.finish(Err(classify_error(&e)));
+                            Err(e)
+                        }
+                    }
+                }
+            },
+            _ => self.intercept_and_send(req, client, profile, client_addr, host, url).await,
+        };
+        self.metrics.observe_request(host, &method, result.as_ref().ok().map(|res| res.status()), start.elapsed());
+        result
+    }
+
+    // Runs the interceptors around the upstream exchange
+    async fn intercept_and_send(
+        &self,
+        req: Request<Incoming>,
+        client: RqClient,
+        profile: Impersonate,
+        client_addr: SocketAddr,
+        host: &str,
+        url: String,
+    ) -> ResponseResult {
+        if self.interceptors.is_empty() {
+            return self.send_upstream(req, client, profile, client_addr, host, url).await;
+        }
+
+        let (mut parts, body) = req.into_parts();
+        for interceptor in &self.interceptors {
+            match interceptor.on_request(&mut parts).await {
+                Action::Continue => {}
+                Action::ShortCircuit(response) => return Ok(response),
+                Action::Block => return self.blocked_response(host),
+            }
+        }
+
+        // Interceptors may have rewritten the URI
+        let url = parts.uri.to_string();
+        let response = self.send_upstream(Request::from_parts(parts, body), client, profile, client_addr, host, url).await?;
+
+        let (mut parts, body) = response.into_parts();
+        for interceptor in &self.interceptors {
+            interceptor.on_response(&mut parts).await;
+        }
+        Ok(Response::from_parts(parts, body))
+    }
+
+    async fn send_upstream(
+        &self,
+        req: Request<Incoming>,
+        client: RqClient,
+        profile: Impersonate,
+        client_addr: SocketAddr,
+        host: &str,
+        url: String,
+    ) -> ResponseResult {
+        let started = OffsetDateTime::now_utc();
+        let start = Instant::now();
+
+        let (mut parts, body) = req.into_parts();
+
+        // gRPC messages are length-prefixed frames on a long-lived stream, so they are never buffered or hooked
+        let grpc = is_grpc(&parts.headers);
+        if grpc {
+            let (service, method) = grpc_call(parts.uri.path()).unwrap_or(("unknown", "unknown"));
+            info!(component = "PROXY", host = %host, "gRPC call {}/{}", service, method);
+        }
+
+        // Request hooks rewrite the body along with the head, so it is only collected up front when there
+        // are any. Otherwise it is streamed upstream as it arrives. Trailers are dropped either way, rquest
+        // has no way to send them upstream.
+        let hooked = !grpc && !self.request_hooks.is_empty();
+        let (mut body, streamed_body) = if hooked {
+            (body.collect().await?.to_bytes(), None)
+        } else if body.is_end_stream() {
+            (Bytes::new(), None)
+        } else {
+            (Bytes::new(), Some(body))
+        };
+        if hooked {
+            for hook in &self.request_hooks {
+                hook.modify_request(&mut parts, &mut body)?;
+            }
+        }
+        let url = if hooked { parts.uri.to_string() } else { url };
+
+        let is_head = parts.method == Method::HEAD;
+        let request_method = parts.method.to_string();
+        let request_headers = self.recorder.as_ref().map(|_| parts.headers.clone());
+
+        // Fetch HEAD requests as GET upstream so the body can populate the cache
+        let head_to_get = is_head && self.cache_head_to_get && self.response_cache.is_some();
+        let method = match to_rquest_method(&parts.method) {
+            Some(_) if head_to_get => RqMethod::GET,
+            Some(method) => method,
+            None => {
+                info!(component = "PROXY", "Rejecting unsupported method {} for {}", parts.method, url);
+                return Ok(Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .body(full(format!("Method {} is not supported", parts.method)))?);
+            }
+        };
+        let is_get = method == RqMethod::GET;
+
+        // Build request with rquest client
+        let mut rq = client.request(method, &url);
+        
+        let mut forward_headers = parts.headers.clone();
+        if self.forwarded_header != ForwardedHeaderMode::Off {
+            let hop = ForwardedHop {
+                client: client_addr.ip(),
+                host: parts.uri.authority().map(|a| a.as_str()).unwrap_or(host),
+                proto: parts.uri.scheme_str().unwrap_or("http"),
+                by: self.proxy_ip,
+            };
+            forwarded::apply(&mut forward_headers, self.forwarded_header, self.forwarded_existing, &hop);
+        }
+
+        // Forward headers except those handled by rquest's profile
+        let sends_client_hints = client_hints(profile).is_some();
+        for (k, v) in forward_headers.iter() {
+            let key_str = k.as_str().to_lowercase();
+            // Only skip headers that would interfere with profile impersonation. Client hints the client sent
+            // itself are kept for Chromium profiles, any other profile would give itself away with them.
+            // Passthrough headers override the profile's value.
+            let profile_header = k == hyper::header::USER_AGENT ||
+               k == hyper::header::ACCEPT ||
+               k == hyper::header::ACCEPT_ENCODING ||
+               k == hyper::header::ACCEPT_LANGUAGE ||
+               (key_str.starts_with("sec-") && !(sends_client_hints && key_str.starts_with("sec-ch-ua")));
+            if k != hyper::header::HOST &&
+               k != hyper::header::PROXY_AUTHORIZATION &&
+               // Recomputed below since hooks may have changed the body
+               k != hyper::header::CONTENT_LENGTH &&
+               (!profile_header || self.passthrough_headers.contains(k)) {
+                rq = rq.header(k, v);
+            }
+        }
+
+        // rquest only covers the TLS and HTTP/2 fingerprint, so add the client hints a Chromium profile would send
+        if let Some(hints) = client_hints(profile) {
+            for (name, value) in hints.headers() {
+                if !parts.headers.contains_key(name) {
+                    rq = rq.header(name, value);
+                }
+            }
+        }
+
+        // Revalidate cached responses with the stored ETag unless the client is doing its own revalidation
+        let cached = match &self.response_cache {
+            Some(cache) if is_get && !parts.headers.contains_key(hyper::header::IF_NONE_MATCH) => cache.get(&url),
+            _ => None,
+        };
+        if let Some(cached) = &cached {
+            rq = rq.header(hyper::header::IF_NONE_MATCH, cached.etag.as_str());
+        }
+
+        // Forward request body. A streamed body keeps the client's Content-Length and goes out chunked
+        // without one, it also can't be replayed so such requests are never retried.
+        let request_body_size = Arc::new(AtomicUsize::new(body.len()));
+        if let Some(streamed_body) = streamed_body {
+            if let Some(length) = parts.headers.get(hyper::header::CONTENT_LENGTH) {
+                rq = rq.header(hyper::header::CONTENT_LENGTH, length);
+            }
+            let metrics = Arc::clone(&self.metrics);
+            let metrics_host = host.to_string();
+            let size = Arc::clone(&request_body_size);
+            let stream = streamed_body.into_data_stream()
+                .inspect_ok(move |chunk| {
+                    metrics.add_bytes_sent(&metrics_host, chunk.len());
+                    size.fetch_add(chunk.len(), Ordering::Relaxed);
+                });
+            rq = rq.body(rquest::Body::wrap_stream(stream));
+        } else if !body.is_empty() {
+            self.metrics.add_bytes_sent(host, body.len());
+            rq = rq.header(hyper::header::CONTENT_LENGTH, body.len().to_string());
+            rq = rq.body(body);
+        }
+
+        // Send request with rquest's profile. send() resolves once the final response head arrives and the
+        // body is still streamed afterwards. Informational 1xx responses such as 103 Early Hints are consumed
+        // by the underlying hyper client and never surfaced by rquest, so they cannot be forwarded and the
+        // client only sees the final response.
+        let safe = [Method::GET, Method::HEAD, Method::OPTIONS, Method::TRACE].contains(&parts.method);
+        let res = self.send_with_retry(rq, &url, safe).await?;
+        let ttfb = start.elapsed();
+
+        if let Some(tls_info) = res.extensions().get::<rquest::tls::TlsInfo>() {
+            self.upstream_certs.observe(host, tls_info);
+        }
+
+        info!(
+            component = "PROXY",
+            host = %host,
+            method = %request_method,
+            status = res.status().as_u16(),
+            duration_ms = ttfb.as_millis() as u64,
+            "{} {} -> {}", request_method, url, res.status()
+        );
+        self.session_manager.record_request(host, RequestRecord {
+            method: request_method.clone(),
+            url: url.clone(),
+            status: res.status().as_u16(),
+            timestamp: started,
+            duration_ms: ttfb.as_millis() as u64,
+        });
+        self.session_manager.publish(host).await;
+
+        if let Some(cached) = cached {
+            if res.status() == StatusCode::NOT_MODIFIED {
+                info!(component = "CACHE", "Upstream returned 304, serving cached response for {}", url);
+                let (mut parts, _) = cached.to_response()?.into_parts();
+                let mut body = cached.body.clone();
+                self.apply_response_hooks(&mut parts, &mut body)?;
+                if is_head {
+                    return Ok(Response::from_parts(parts, empty()));
+                }
+                return Ok(Response::from_parts(parts, full(body)));
+            }
+        }
+
+        // Convert response
+        let status = res.status();
+        let headers = res.headers().clone();
+        let mut builder = Response::builder()
+            .status(status);
+
+        // Forward all response headers
+        for (k, v) in headers.iter() {
+            builder = builder.header(k, v);
+        }
+
+        // The gRPC status arrives in trailers, so the body is passed on frame by frame with them included
+        if grpc && !is_head {
+            let metrics = Arc::clone(&self.metrics);
+            let metrics_host = host.to_string();
+            let body = rquest::Body::from(res)
+                .map_frame(move |frame| {
+                    if let Some(data) = frame.data_ref() {
+                        metrics.add_bytes_received(&metrics_host, data.len());
+                    }
+                    frame
+                })
+                .map_err(Error::from)
+                .boxed_unsync();
+            return Ok(builder.body(body)?);
+        }
+
+        // Buffering server-sent events would hang, hyper writes each event out as soon as it arrives
+        if is_event_stream(&headers) && !is_head {
+            debug!(component = "PROXY", "Streaming server-sent events from {}", url);
+            let stream = res.stream_counted(Arc::clone(&self.metrics), host.to_string());
+            return Ok(match self.sse_timeout {
+                Some(timeout) => builder.body(streaming(stream.take_until(tokio::time::sleep(timeout))))?,
+                None => builder.body(streaming(stream))?,
+            });
+        }
+
+        // Buffer the body only when it is going into the cache, the HAR recording or a response hook,
+        // otherwise stream it through
+        let cacheable = self.response_cache.is_some() && is_get && status == StatusCode::OK
+            && headers.contains_key(hyper::header::ETAG);
+        if cacheable || self.recorder.is_some() || !self.response_hooks.is_empty() {
+            let mut body = match self.max_response_buffer {
+                None => res.bytes_counted(&self.metrics, host).await?.0,
+                Some(limit) => {
+                    // Past the limit the body is streamed on unchanged, skipping the cache, recording and hooks
+                    let mut stream = Box::pin(res.stream_counted(Arc::clone(&self.metrics), host.to_string()));
+                    let mut buffered = BytesMut::new();
+                    loop {
+                        let Some(chunk) = stream.try_next().await? else {
+                            break buffered.freeze();
+                        };
+                        buffered.extend_from_slice(&chunk);
+                        if buffered.len() > limit {
+                            info!(component = "PROXY", "Response for {} is larger than {} bytes, streaming it unmodified", url, limit);
+                            if is_head {
+                                return Ok(builder.body(empty())?);
+                            }
+                            let body = futures_util::stream::once(async move { Ok(buffered.freeze()) }).chain(stream);
+                            return Ok(builder.body(streaming(body))?);
+                        }
+                    }
+                }
+            };
+
+            if let (Some(cache), true) = (&self.response_cache, cacheable) {
+                cache.store(&url, &headers, &body);
+            }
+            if let (Some(recorder), Some(request_headers)) = (&self.recorder, &request_headers) {
+                recorder.record(Exchange {
+                    started,
+                    method: &request_method,
+                    url: &url,
+                    request_headers,
+                    request_body_size: request_body_size.load(Ordering::Relaxed),
+                    status,
+                    response_headers: &headers,
+                    response_body: &body,
+                    ttfb,
+                    total: start.elapsed(),
+                });
+            }
+
+            // Hooks run after caching and recording so both keep what the upstream actually sent
+            let (mut parts, _) = builder.body(())?.into_parts();
+            self.apply_response_hooks(&mut parts, &mut body)?;
+            if is_head {
+                return Ok(Response::from_parts(parts, empty()));
+            }
+            return Ok(Response::from_parts(parts, full(body)));
+        }
+
+        // The client asked for HEAD, so only the headers (including Content-Length) go back
+        if is_head {
+            return Ok(builder.body(empty())?);
+        }
+
+        // Content-Length is forwarded when the upstream sent it, otherwise hyper falls back to chunked
+        let stream = res.stream_counted(Arc::clone(&self.metrics), host.to_string());
+        Ok(builder.body(streaming(stream))?)
+    }
+
     pub async fn handle_request(
         self: Arc<Self>,
         req: Request<Incoming>,
+        client_addr: SocketAddr,
     ) -> ResponseResult {
+        // Credentials are checked on the CONNECT itself, requests inside the tunnel are already trusted
+        if let Some(auth) = &self.proxy_auth {
+            if !auth.is_authorized(req.headers()) {
+                info!(component = "AUTH", "Rejecting unauthenticated {} from {}", req.method(), client_addr);
+                return proxy_auth_required();
+            }
+        }
+
         if req.method() == Method::CONNECT {
-            // Handle CONNECT for HTTPS
-            let host = req.uri().authority()
-                .ok_or("No authority in CONNECT request")?
-                .host()
-                .to_string();
+            // Handle CONNECT for HTTPS. Certificates and sessions are keyed on the host, but a
+            // non-standard port has to stay in the URLs requests are forwarded to.
+            let authority = req.uri().authority()
+                .ok_or("No authority in CONNECT request")?;
+            let host = authority.host().to_string();
+            let upstream_authority = match authority.port_u16() {
+                Some(port) if port != 443 => format!("{}:{}", host, port),
+                _ => host.clone(),
+            };
+
+            // Refuse the tunnel before doing any TLS work for blocked hosts
+            if self.is_blocked(&host) {
+                return self.blocked_response(&host);
+            }
+
+            // Pinned sites would reject the generated certificate, so their bytes are relayed as they are
+            if self.passthrough.matches(&host) {
+                let port = authority.port_u16().unwrap_or(443);
+                return self.passthrough_tunnel(req, &host, port);
+            }
 
             // Create server config for the domain
-            let server_config = Arc::new(self.create_server_config(&host)?);
+            let server_config = Arc::new(self.create_server_config(&host).await?);
             let acceptor = tokio_rustls::TlsAcceptor::from(server_config);
 
             // Get the upgrade handle before sending response
@@ -121,13 +1033,40 @@ impl Proxy {
             // Send 200 response to establish tunnel
             let response = Response::new(empty());
 
-            // Spawn task to handle the upgraded connection
+            // Spawn task to handle the upgraded connection, tracked so shutdown can wait for it
             let self_clone = Arc::clone(&self);
-            tokio::spawn(async move {
+            let shutdown = self.shutdown.clone();
+            let max_request_headers = self.max_request_headers;
+            self.connections.spawn(async move {
                 match upgrade.await {
                     Ok(upgraded) => {
                         let io = hyper_util::rt::TokioIo::new(upgraded);
 
+                        // At debug level the ClientHello is read ahead of rustls so the client's JA3 can be logged,
+                        // then replayed to it. This is the inbound fingerprint, the one sent upstream is built
+                        // inside rquest and can't be observed here.
+                        let io = if tracing::enabled!(tracing::Level::DEBUG) {
+                            match fingerprint::read_client_hello(io).await {
+                                Ok((client_hello, io)) => {
+                                    match client_hello.as_deref().map(Ja3Fingerprint::compute) {
+                                        Some(Ok(ja3)) => debug!(
+                                            component = "TLS", host = %host, ja3 = %ja3.hash,
+                                            "Inbound client JA3 for {} (not the upstream fingerprint): {} ({})", host, ja3.hash, ja3.text
+                                        ),
+                                        Some(Err(e)) => debug!(component = "TLS", "No inbound JA3 for {}: {}", host, e),
+                                        None => debug!(component = "TLS", "No TLS ClientHello record from the client for {}", host),
+                                    }
+                                    io
+                                }
+                                Err(e) => {
+                                    error!(component = "PROXY", "Failed to read TLS ClientHello for {}: {}", host, e);
+                                    return;
+                                }
+                            }
+                        } else {
+                            Rewind::new(io)
+                        };
+
                         // Accept TLS connection
                         match acceptor.accept(io).await {
                             Ok(tls_stream) => {
@@ -138,6 +1077,7 @@ impl Proxy {
                                 let service = hyper::service::service_fn(move |mut req| {
                                     let self_clone = Arc::clone(&self_clone);
                                     let host = host_str.clone();
+                                    let upstream_authority = upstream_authority.clone();
                                     async move {
                                         let result = async {
                                             // Add scheme and authority if missing
@@ -145,25 +1085,16 @@ impl Proxy {
                                                 let mut parts = req.uri().clone().into_parts();
                                                 parts.scheme = Some(hyper::http::uri::Scheme::HTTPS);
                                                 if parts.authority.is_none() {
-                                                    parts.authority = Some(host.parse().map_err(|e| Box::new(e) as Error)?);
+                                                    parts.authority = Some(upstream_authority.parse().map_err(|e| Box::new(e) as Error)?);
                                                 }
                                                 *req.uri_mut() = hyper::http::uri::Uri::from_parts(parts)
                                                     .map_err(|e| Box::new(e) as Error)?;
                                             }
 
-                                            // Forward request using rquest
                                             let url = req.uri().to_string();
-                                            let method = match *req.method() {
-                                                Method::GET => RqMethod::GET,
-                                                Method::POST => RqMethod::POST,
-                                                Method::PUT => RqMethod::PUT,
-                                                Method::DELETE => RqMethod::DELETE,
-                                                Method::PATCH => RqMethod::PATCH,
-                                                _ => RqMethod::GET,
-                                            };
 
                                             // Get or create session for this host
-                                            let client = self_clone.session_manager.get_or_create_session(&host)?;
+                                            let (client, profile) = self_clone.session_manager.session_for(&host).await?;
 
                                             // Check if this is a valid WebSocket upgrade request
                                             let is_websocket = req.headers().get(hyper::header::UPGRADE)
@@ -178,59 +1109,21 @@ impl Proxy {
                                                 && req.headers().get("Sec-WebSocket-Version").is_some();
 
                                             if is_websocket {
-                                                log("WS", &format!("Valid WebSocket upgrade request for {}", url));
+                                                info!(component = "WS", "Valid WebSocket upgrade request for {}", url);
                                                 return self_clone.handle_websocket_request(req, client, url).await;
                                             }
-
-                                            // Build request with rquest client
-                                            let mut rq = client.request(method, &url);
-                                            
-                                            // Forward headers except those handled by rquest's profile
-                                            for (k, v) in req.headers() {
-                                                let key_str = k.as_str().to_lowercase();
-                                                // Only skip headers that would interfere with profile impersonation
-                                                if k != hyper::header::USER_AGENT && 
-                                                   k != hyper::header::ACCEPT && 
-                                                   k != hyper::header::ACCEPT_ENCODING && 
-                                                   k != hyper::header::ACCEPT_LANGUAGE && 
-                                                   k != hyper::header::HOST &&
-                                                   !key_str.starts_with("sec-") {
-                                                    rq = rq.header(k, v);
-                                                }
-                                            }
-
-                                            // Forward request method and body
-                                            let body = req.into_body().collect().await.map_err(|e| Box::new(e) as Error)?.to_bytes();
-                                            if !body.is_empty() {
-                                                rq = rq.header(hyper::header::CONTENT_LENGTH, body.len().to_string());
-                                                rq = rq.body(body);
-                                            }
-
-                                            // Send request with rquest's profile
-                                            let res = rq.send().await.map_err(|e| Box::new(e) as Error)?;
-
-                                            // Convert response
-                                            let mut builder = Response::builder()
-                                                .status(res.status());
-
-                                            // Forward all response headers
-                                            for (k, v) in res.headers() {
-                                                builder = builder.header(k, v);
+                                            if let Some(protocol) = raw_upgrade_protocol(&req) {
+                                                return self_clone.handle_raw_upgrade(req, client, url, protocol).await;
                                             }
 
-                                            let body = res.bytes().await.map_err(|e| Box::new(e) as Error)?;
-                                            Ok::<_, Error>(builder.body(full(body))
-                                                .map_err(|e| Box::new(e) as Error)?)
+                                            self_clone.forward_request(req, client, profile, client_addr, &host, url).await
                                         }.await;
 
                                         match result {
                                             Ok(res) => Ok::<_, std::convert::Infallible>(res),
                                             Err(e) => {
-                                                eprintln!("[ERROR] HTTPS request failed: {}", e);
-                                                Ok(Response::builder()
-                                                    .status(500)
-                                                    .body(full(format!("Error: {}", e)))
-                                                    .unwrap())
+                                                error!(component = "PROXY", "HTTPS request failed: {}", e);
+                                                Ok(error_response(&e))
                                             }
                                         }
                                     }
@@ -241,27 +1134,45 @@ impl Proxy {
                                 let alpn = tls_stream.get_ref().1.alpn_protocol() == Some(b"h2");
                                 let io = hyper_util::rt::TokioIo::new(tls_stream);
 
+                                // HTTP/2 requests arrive with :scheme and :authority set, so the URI fix-up above
+                                // is a no-op for them. Request bodies are collected the same way as over HTTP/1.1.
+                                // On shutdown the connection finishes its in-flight requests and then closes
                                 let result = if alpn {
-                                    hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
-                                        .serve_connection(io, service)
-                                        .await
+                                    let conn = hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
+                                        .serve_connection(io, service);
+                                    tokio::pin!(conn);
+                                    tokio::select! {
+                                        result = conn.as_mut() => result,
+                                        _ = shutdown.cancelled() => {
+                                            conn.as_mut().graceful_shutdown();
+                                            conn.await
+                                        }
+                                    }
                                 } else {
-                                    hyper::server::conn::http1::Builder::new()
+                                    let conn = hyper::server::conn::http1::Builder::new()
+                                        .max_headers(max_request_headers)
                                         .preserve_header_case(true)
                                         .title_case_headers(true)
                                         .serve_connection(io, service)
-                                        .with_upgrades()
-                                        .await
+                                        .with_upgrades();
+                                    tokio::pin!(conn);
+                                    tokio::select! {
+                                        result = conn.as_mut() => result,
+                                        _ = shutdown.cancelled() => {
+                                            conn.as_mut().graceful_shutdown();
+                                            conn.await
+                                        }
+                                    }
                                 };
 
                                 if let Err(e) = result {
-                                    eprintln!("[ERROR] HTTPS connection failed: {}", e);
+                                    error!(component = "PROXY", "HTTPS connection failed: {}", e);
                                 }
                             }
-                            Err(e) => eprintln!("[ERROR] TLS accept failed: {}", e),
+                            Err(e) => error!(component = "PROXY", "TLS accept failed: {}", e),
                         }
                     }
-                    Err(e) => eprintln!("[ERROR] Connection upgrade failed: {}", e),
+                    Err(e) => error!(component = "PROXY", "Connection upgrade failed: {}", e),
                 }
             });
 
@@ -276,56 +1187,34 @@ impl Proxy {
                 .host()
                 .to_string();
 
-            // Get or create session for this host
-            let client = self.session_manager.get_or_create_session(&host)?;
-
-            let method = match *req.method() {
-                Method::GET => RqMethod::GET,
-                Method::POST => RqMethod::POST,
-                Method::PUT => RqMethod::PUT,
-                Method::DELETE => RqMethod::DELETE,
-                Method::PATCH => RqMethod::PATCH,
-                _ => RqMethod::GET,
-            };
-
-            // Build request with rquest client
-            let mut rq = client.request(method, &url);
-            
-            // Forward headers except those handled by rquest's profile
-            for (k, v) in req.headers() {
-                let key_str = k.as_str().to_lowercase();
-                // Only skip headers that would interfere with profile impersonation
-                if k != hyper::header::USER_AGENT && 
-                   k != hyper::header::ACCEPT && 
-                   k != hyper::header::ACCEPT_ENCODING && 
-                   k != hyper::header::ACCEPT_LANGUAGE && 
-                   k != hyper::header::HOST &&
-                   !key_str.starts_with("sec-") {
-                    rq = rq.header(k, v);
-                }
+            if host.eq_ignore_ascii_case(CA_HOST) {
+                info!(component = "CERT", "Serving CA certificate {} to {}", req.uri().path(), client_addr);
+                return self.ca_cert_response(req.uri().path());
             }
 
-            // Forward request method and body
-            let body = req.into_body().collect().await?.to_bytes();
-            if !body.is_empty() {
-                rq = rq.header(hyper::header::CONTENT_LENGTH, body.len().to_string());
-                rq = rq.body(body);
+            if self.is_blocked(&host) {
+                return self.blocked_response(&host);
             }
 
-            // Send request with rquest's profile
-            let res = rq.send().await?;
+            // A browser would never send plain HTTP to an HSTS preloaded host, so send the client to HTTPS
+            if self.hsts_preload.as_ref().is_some_and(|hsts| hsts.is_preloaded(&host)) {
+                let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+                let location = format!("https://{}{}", host, path);
+                info!(component = "PROXY", "Redirecting {} to {} (HSTS preloaded)", url, location);
+                return Ok(Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(hyper::header::LOCATION, location)
+                    .body(empty())?);
+            }
 
-            // Convert response
-            let mut builder = Response::builder()
-                .status(res.status());
+            // Get or create session for this host
+            let (client, profile) = self.session_manager.session_for(&host).await?;
 
-            // Forward all response headers
-            for (k, v) in res.headers() {
-                builder = builder.header(k, v);
+            if let Some(protocol) = raw_upgrade_protocol(&req) {
+                return self.handle_raw_upgrade(req, client, url, protocol).await;
             }
 
-            let body = res.bytes().await?;
-            Ok(builder.body(full(body))?)
+            self.forward_request(req, client, profile, client_addr, &host, url).await
         }
     }
 }
@@ -0,0 +1,200 @@
+use base64::Engine;
+use bytes::Bytes;
+use hyper::{HeaderMap, StatusCode};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use crate::types::Error;
+use tracing::info;
+
+// A single request/response pair in HAR 1.2 format
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    started_date_time: String,
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: HarCache,
+    timings: HarTimings,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<HarHeader>,
+    headers: Vec<HarHeader>,
+    query_string: Vec<HarHeader>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    cookies: Vec<HarHeader>,
+    headers: Vec<HarHeader>,
+    content: HarContent,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct HarContent {
+    size: i64,
+    mime_type: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize, Clone)]
+struct HarCache {}
+
+#[derive(Serialize, Clone)]
+struct HarTimings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+// Everything known about an exchange once the response body has been read
+pub struct Exchange<'a> {
+    pub started: OffsetDateTime,
+    pub method: &'a str,
+    pub url: &'a str,
+    pub request_headers: &'a HeaderMap,
+    pub request_body_size: usize,
+    pub status: StatusCode,
+    pub response_headers: &'a HeaderMap,
+    pub response_body: &'a Bytes,
+    pub ttfb: Duration,
+    pub total: Duration,
+}
+
+fn har_headers(headers: &HeaderMap) -> Vec<HarHeader> {
+    headers.iter()
+        .map(|(k, v)| HarHeader {
+            name: k.to_string(),
+            value: String::from_utf8_lossy(v.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+fn har_query(url: &str) -> Vec<HarHeader> {
+    url::Url::parse(url)
+        .map(|url| url.query_pairs()
+            .map(|(name, value)| HarHeader { name: name.into_owned(), value: value.into_owned() })
+            .collect())
+        .unwrap_or_default()
+}
+
+// Records proxied traffic in memory for export as a HAR file
+#[derive(Clone, Default)]
+pub struct HarRecorder {
+    entries: Arc<Mutex<Vec<HarEntry>>>,
+}
+
+impl HarRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, exchange: Exchange<'_>) {
+        let mime_type = exchange.response_headers.get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        // Binary bodies are stored base64 encoded as allowed by the spec
+        let (text, encoding) = match std::str::from_utf8(exchange.response_body) {
+            Ok(text) => (text.to_string(), None),
+            Err(_) => (
+                base64::engine::general_purpose::STANDARD.encode(exchange.response_body),
+                Some("base64".to_string()),
+            ),
+        };
+
+        let ttfb_ms = exchange.ttfb.as_secs_f64() * 1000.0;
+        let total_ms = exchange.total.as_secs_f64() * 1000.0;
+
+        let entry = HarEntry {
+            started_date_time: exchange.started.format(&Rfc3339).unwrap_or_default(),
+            time: total_ms,
+            request: HarRequest {
+                method: exchange.method.to_string(),
+                url: exchange.url.to_string(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: har_headers(exchange.request_headers),
+                query_string: har_query(exchange.url),
+                headers_size: -1,
+                body_size: exchange.request_body_size as i64,
+            },
+            response: HarResponse {
+                status: exchange.status.as_u16(),
+                status_text: exchange.status.canonical_reason().unwrap_or("").to_string(),
+                http_version: "HTTP/1.1".to_string(),
+                cookies: Vec::new(),
+                headers: har_headers(exchange.response_headers),
+                content: HarContent {
+                    size: exchange.response_body.len() as i64,
+                    mime_type,
+                    text,
+                    encoding,
+                },
+                redirect_url: exchange.response_headers.get(hyper::header::LOCATION)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("")
+                    .to_string(),
+                headers_size: -1,
+                body_size: exchange.response_body.len() as i64,
+            },
+            cache: HarCache {},
+            timings: HarTimings {
+                send: 0.0,
+                wait: ttfb_ms,
+                receive: total_ms - ttfb_ms,
+            },
+        };
+
+        self.entries.lock().push(entry);
+    }
+
+    pub fn export(&self, path: &Path) -> Result<(), Error> {
+        let entries = self.entries.lock().clone();
+        let har = serde_json::json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": "boring-proxy",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "entries": entries,
+            }
+        });
+
+        std::fs::write(path, serde_json::to_vec_pretty(&har)?)?;
+        info!(component = "HAR", "Exported {} entries to {}", entries.len(), path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use crate::session_manager::SavedSession;
+use crate::types::Error;
+use tracing::info;
+
+const KEY_PREFIX: &str = "boring-proxy:session:";
+
+// Sessions shared between proxy instances through Redis. Only the profile and cookies are stored,
+// each instance builds its own client from them.
+pub struct RedisSessionStore {
+    conn: ConnectionManager,
+}
+
+impl RedisSessionStore {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url)
+            .map_err(|e| format!("Invalid Redis URL: {}", e))?;
+        let conn = ConnectionManager::new(client).await
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        info!(component = "SESSION", "Sharing sessions through Redis");
+        Ok(Self { conn })
+    }
+
+    pub(crate) async fn load(&self, host: &str) -> Result<Option<SavedSession>, Error> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.get(format!("{}{}", KEY_PREFIX, host)).await?;
+        Ok(value.map(|value| serde_json::from_str(&value)).transpose()?)
+    }
+
+    // Entries expire with the session TTL so instances don't pick up sessions that were already dropped
+    pub(crate) async fn store(&self, session: &SavedSession, ttl: Duration) -> Result<(), Error> {
+        let mut conn = self.conn.clone();
+        let _: () = conn.set_ex(
+            format!("{}{}", KEY_PREFIX, session.host),
+            serde_json::to_string(session)?,
+            ttl.as_secs().max(1),
+        ).await?;
+        Ok(())
+    }
+}
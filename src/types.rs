@@ -1,20 +1,73 @@
 use std::error::Error as StdError;
-use http_body_util::{Empty, Full, combinators::BoxBody};
-use rquest::Impersonate;
-use std::convert::Infallible;
+use http_body_util::{BodyExt, Empty, Full, StreamBody, combinators::UnsyncBoxBody};
+use hyper::body::Frame;
+use futures_util::{Stream, TryStreamExt};
+use hyper::{Method, StatusCode};
+use rquest::{Impersonate, Method as RqMethod};
 use bytes::Bytes;
 
 pub type Error = Box<dyn StdError + Send + Sync + 'static>;
-pub type ResponseBody = BoxBody<Bytes, Infallible>;
+pub type ResponseBody = UnsyncBoxBody<Bytes, Error>;
 pub type ResponseResult = Result<hyper::Response<ResponseBody>, Error>;
 
+// Default limit on request headers, requests with more get 431 Request Header Fields Too Large
+pub const MAX_HEADERS: usize = 64;
+
 // Helper functions for body conversion
 pub fn empty() -> ResponseBody {
-    BoxBody::new(Empty::<Bytes>::new())
+    Empty::<Bytes>::new()
+        .map_err(|never| match never {})
+        .boxed_unsync()
 }
 
 pub fn full<T: Into<Bytes>>(data: T) -> ResponseBody {
-    BoxBody::new(Full::new(data.into()))
+    Full::new(data.into())
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
+
+// Status and client-safe message for a failed request, the full error is only logged
+pub fn classify_error(e: &Error) -> (StatusCode, &'static str) {
+    match e.downcast_ref::<rquest::Error>() {
+        Some(e) if e.is_timeout() => (StatusCode::GATEWAY_TIMEOUT, "Upstream timed out"),
+        Some(e) if e.is_connect() => (StatusCode::BAD_GATEWAY, "Could not connect to upstream"),
+        Some(e) if e.is_decode() || e.is_body() => (StatusCode::BAD_GATEWAY, "Invalid response from upstream"),
+        Some(_) => (StatusCode::BAD_GATEWAY, "Upstream request failed"),
+        None => (StatusCode::INTERNAL_SERVER_ERROR, "Internal proxy error"),
+    }
+}
+
+pub fn error_response(e: &Error) -> hyper::Response<ResponseBody> {
+    let (status, message) = classify_error(e);
+    hyper::Response::builder()
+        .status(status)
+        .body(full(message))
+        .expect("status and body are always valid")
+}
+
+// Forward chunks to the client as they arrive instead of buffering the whole body
+pub fn streaming<S, E>(stream: S) -> ResponseBody
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<Error>,
+{
+    StreamBody::new(stream.map_ok(Frame::data).map_err(Into::into))
+        .boxed_unsync()
+}
+
+// Map an inbound method to the outbound one, None for methods we don't forward
+pub fn to_rquest_method(method: &Method) -> Option<RqMethod> {
+    Some(match *method {
+        Method::GET => RqMethod::GET,
+        Method::HEAD => RqMethod::HEAD,
+        Method::POST => RqMethod::POST,
+        Method::PUT => RqMethod::PUT,
+        Method::DELETE => RqMethod::DELETE,
+        Method::PATCH => RqMethod::PATCH,
+        Method::OPTIONS => RqMethod::OPTIONS,
+        Method::TRACE => RqMethod::TRACE,
+        _ => return None,
+    })
 }
 
 // Available browser profiles for rotation
@@ -84,11 +137,199 @@ pub const PROFILES: &[Impersonate] = &[
     Impersonate::OkHttp3_9,
 ];
 
-pub fn log(component: &str, message: &str) {
-    use std::time::{SystemTime, UNIX_EPOCH, Duration};
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_millis();
-    println!("[{}][{}] {}", timestamp, component, message);
+// Look up a profile by its variant name, e.g. "Chrome131"
+pub fn profile_from_str(name: &str) -> Option<Impersonate> {
+    PROFILES.iter()
+        .copied()
+        .find(|profile| format!("{:?}", profile) == name)
+}
+
+// Client hint headers a browser profile sends alongside its TLS fingerprint
+pub struct ClientHints {
+    pub sec_ch_ua: &'static str,
+    pub full_version_list: &'static str,
+    pub mobile: &'static str,
+    pub platform: &'static str,
+}
+
+impl ClientHints {
+    pub fn headers(&self) -> [(&'static str, &'static str); 4] {
+        [
+            ("sec-ch-ua", self.sec_ch_ua),
+            ("sec-ch-ua-mobile", self.mobile),
+            ("sec-ch-ua-platform", self.platform),
+            ("sec-ch-ua-full-version-list", self.full_version_list),
+        ]
+    }
+}
+
+const fn desktop_hints(sec_ch_ua: &'static str, full_version_list: &'static str) -> ClientHints {
+    ClientHints {
+        sec_ch_ua,
+        full_version_list,
+        mobile: "?0",
+        platform: "\"macOS\"",
+    }
+}
+
+// Chromium based profiles and the hints the matching browser release sends, other families send none
+const CLIENT_HINTS: &[(Impersonate, ClientHints)] = &[
+    (Impersonate::Chrome131, desktop_hints(
+        r#""Google Chrome";v="131", "Chromium";v="131", "Not_A Brand";v="24""#,
+        r#""Google Chrome";v="131.0.6778.86", "Chromium";v="131.0.6778.86", "Not_A Brand";v="24.0.0.0""#,
+    )),
+    (Impersonate::Chrome130, desktop_hints(
+        r#""Chromium";v="130", "Google Chrome";v="130", "Not?A_Brand";v="99""#,
+        r#""Chromium";v="130.0.6723.92", "Google Chrome";v="130.0.6723.92", "Not?A_Brand";v="99.0.0.0""#,
+    )),
+    (Impersonate::Chrome129, desktop_hints(
+        r#""Google Chrome";v="129", "Not=A?Brand";v="8", "Chromium";v="129""#,
+        r#""Google Chrome";v="129.0.6668.90", "Not=A?Brand";v="8.0.0.0", "Chromium";v="129.0.6668.90""#,
+    )),
+    (Impersonate::Chrome128, desktop_hints(
+        r#""Chromium";v="128", "Not;A=Brand";v="24", "Google Chrome";v="128""#,
+        r#""Chromium";v="128.0.6613.138", "Not;A=Brand";v="24.0.0.0", "Google Chrome";v="128.0.6613.138""#,
+    )),
+    (Impersonate::Chrome127, desktop_hints(
+        r#""Not)A;Brand";v="99", "Google Chrome";v="127", "Chromium";v="127""#,
+        r#""Not)A;Brand";v="99.0.0.0", "Google Chrome";v="127.0.6533.120", "Chromium";v="127.0.6533.120""#,
+    )),
+    (Impersonate::Chrome126, desktop_hints(
+        r#""Not/A)Brand";v="8", "Chromium";v="126", "Google Chrome";v="126""#,
+        r#""Not/A)Brand";v="8.0.0.0", "Chromium";v="126.0.6478.127", "Google Chrome";v="126.0.6478.127""#,
+    )),
+    (Impersonate::Chrome124, desktop_hints(
+        r#""Chromium";v="124", "Google Chrome";v="124", "Not-A.Brand";v="99""#,
+        r#""Chromium";v="124.0.6367.207", "Google Chrome";v="124.0.6367.207", "Not-A.Brand";v="99.0.0.0""#,
+    )),
+    (Impersonate::Chrome123, desktop_hints(
+        r#""Google Chrome";v="123", "Not:A-Brand";v="8", "Chromium";v="123""#,
+        r#""Google Chrome";v="123.0.6312.122", "Not:A-Brand";v="8.0.0.0", "Chromium";v="123.0.6312.122""#,
+    )),
+    (Impersonate::Chrome120, desktop_hints(
+        r#""Not_A Brand";v="8", "Chromium";v="120", "Google Chrome";v="120""#,
+        r#""Not_A Brand";v="8.0.0.0", "Chromium";v="120.0.6099.225", "Google Chrome";v="120.0.6099.225""#,
+    )),
+    (Impersonate::Chrome119, desktop_hints(
+        r#""Google Chrome";v="119", "Chromium";v="119", "Not?A_Brand";v="24""#,
+        r#""Google Chrome";v="119.0.6045.199", "Chromium";v="119.0.6045.199", "Not?A_Brand";v="24.0.0.0""#,
+    )),
+    (Impersonate::Chrome118, desktop_hints(
+        r#""Chromium";v="118", "Google Chrome";v="118", "Not=A?Brand";v="99""#,
+        r#""Chromium";v="118.0.5993.118", "Google Chrome";v="118.0.5993.118", "Not=A?Brand";v="99.0.0.0""#,
+    )),
+    (Impersonate::Chrome117, desktop_hints(
+        r#""Google Chrome";v="117", "Not;A=Brand";v="8", "Chromium";v="117""#,
+        r#""Google Chrome";v="117.0.5938.149", "Not;A=Brand";v="8.0.0.0", "Chromium";v="117.0.5938.149""#,
+    )),
+    (Impersonate::Chrome116, desktop_hints(
+        r#""Chromium";v="116", "Not)A;Brand";v="24", "Google Chrome";v="116""#,
+        r#""Chromium";v="116.0.5845.187", "Not)A;Brand";v="24.0.0.0", "Google Chrome";v="116.0.5845.187""#,
+    )),
+    (Impersonate::Chrome114, desktop_hints(
+        r#""Not.A/Brand";v="8", "Chromium";v="114", "Google Chrome";v="114""#,
+        r#""Not.A/Brand";v="8.0.0.0", "Chromium";v="114.0.5735.198", "Google Chrome";v="114.0.5735.198""#,
+    )),
+    (Impersonate::Chrome109, desktop_hints(
+        r#""Not_A Brand";v="99", "Google Chrome";v="109", "Chromium";v="109""#,
+        r#""Not_A Brand";v="99.0.0.0", "Google Chrome";v="109.0.5414.120", "Chromium";v="109.0.5414.120""#,
+    )),
+    (Impersonate::Chrome108, desktop_hints(
+        r#""Not?A_Brand";v="8", "Chromium";v="108", "Google Chrome";v="108""#,
+        r#""Not?A_Brand";v="8.0.0.0", "Chromium";v="108.0.5359.125", "Google Chrome";v="108.0.5359.125""#,
+    )),
+    (Impersonate::Chrome107, desktop_hints(
+        r#""Google Chrome";v="107", "Chromium";v="107", "Not=A?Brand";v="24""#,
+        r#""Google Chrome";v="107.0.5304.122", "Chromium";v="107.0.5304.122", "Not=A?Brand";v="24.0.0.0""#,
+    )),
+    (Impersonate::Chrome106, desktop_hints(
+        r#""Chromium";v="106", "Google Chrome";v="106", "Not;A=Brand";v="99""#,
+        r#""Chromium";v="106.0.5249.119", "Google Chrome";v="106.0.5249.119", "Not;A=Brand";v="99.0.0.0""#,
+    )),
+    (Impersonate::Chrome105, desktop_hints(
+        r#""Google Chrome";v="105", "Not)A;Brand";v="8", "Chromium";v="105""#,
+        r#""Google Chrome";v="105.0.5195.127", "Not)A;Brand";v="8.0.0.0", "Chromium";v="105.0.5195.127""#,
+    )),
+    (Impersonate::Chrome104, desktop_hints(
+        r#""Chromium";v="104", " Not A;Brand";v="99", "Google Chrome";v="104""#,
+        r#""Chromium";v="104.0.5112.102", " Not A;Brand";v="99.0.0.0", "Google Chrome";v="104.0.5112.102""#,
+    )),
+    (Impersonate::Chrome101, desktop_hints(
+        r#"" Not A;Brand";v="99", "Chromium";v="101", "Google Chrome";v="101""#,
+        r#"" Not A;Brand";v="99.0.0.0", "Chromium";v="101.0.4951.67", "Google Chrome";v="101.0.4951.67""#,
+    )),
+    (Impersonate::Chrome100, desktop_hints(
+        r#"" Not A;Brand";v="99", "Chromium";v="100", "Google Chrome";v="100""#,
+        r#"" Not A;Brand";v="99.0.0.0", "Chromium";v="100.0.4896.127", "Google Chrome";v="100.0.4896.127""#,
+    )),
+    (Impersonate::Edge131, desktop_hints(
+        r#""Microsoft Edge";v="131", "Chromium";v="131", "Not_A Brand";v="24""#,
+        r#""Microsoft Edge";v="131.0.2903.86", "Chromium";v="131.0.6778.86", "Not_A Brand";v="24.0.0.0""#,
+    )),
+    (Impersonate::Edge127, desktop_hints(
+        r#""Not)A;Brand";v="99", "Microsoft Edge";v="127", "Chromium";v="127""#,
+        r#""Not)A;Brand";v="99.0.0.0", "Microsoft Edge";v="127.0.2651.74", "Chromium";v="127.0.6533.73""#,
+    )),
+    (Impersonate::Edge122, desktop_hints(
+        r#""Chromium";v="122", "Not(A:Brand";v="24", "Microsoft Edge";v="122""#,
+        r#""Chromium";v="122.0.6261.112", "Not(A:Brand";v="24.0.0.0", "Microsoft Edge";v="122.0.2365.92""#,
+    )),
+    (Impersonate::Edge101, desktop_hints(
+        r#"" Not A;Brand";v="99", "Chromium";v="101", "Microsoft Edge";v="101""#,
+        r#"" Not A;Brand";v="99.0.0.0", "Chromium";v="101.0.4951.64", "Microsoft Edge";v="101.0.1210.47""#,
+    )),
+];
+
+// Client hints for a profile, None for browsers that do not send them (Safari, Firefox, OkHttp)
+pub fn client_hints(profile: Impersonate) -> Option<&'static ClientHints> {
+    CLIENT_HINTS.iter()
+        .find(|(p, _)| *p == profile)
+        .map(|(_, hints)| hints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn head_stays_head() {
+        assert_eq!(to_rquest_method(&Method::HEAD), Some(RqMethod::HEAD));
+        assert_eq!(to_rquest_method(&Method::OPTIONS), Some(RqMethod::OPTIONS));
+        assert_eq!(to_rquest_method(&Method::TRACE), Some(RqMethod::TRACE));
+    }
+
+    #[test]
+    fn unknown_methods_are_not_forwarded() {
+        assert_eq!(to_rquest_method(&Method::CONNECT), None);
+        assert_eq!(to_rquest_method(&Method::from_bytes(b"PROPFIND").unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn timeout_is_504() {
+        // Accepts the connection and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _stream = listener.accept().await;
+            std::future::pending::<()>().await;
+        });
+
+        let client = rquest::Client::builder().timeout(std::time::Duration::from_millis(200)).build().unwrap();
+        let e: Error = client.get(format!("http://{}/", addr)).send().await.unwrap_err().into();
+        assert_eq!(classify_error(&e), (StatusCode::GATEWAY_TIMEOUT, "Upstream timed out"));
+    }
+
+    #[tokio::test]
+    async fn dns_failure_is_502() {
+        // .invalid never resolves (RFC 6761)
+        let client = rquest::Client::builder().build().unwrap();
+        let e: Error = client.get("http://upstream.invalid/").send().await.unwrap_err().into();
+        assert_eq!(classify_error(&e), (StatusCode::BAD_GATEWAY, "Could not connect to upstream"));
+    }
+
+    #[test]
+    fn proxy_errors_are_500() {
+        let e: Error = "No authority in request".into();
+        assert_eq!(classify_error(&e).0, StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
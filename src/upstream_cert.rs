@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use boring2::{hash::MessageDigest, nid::Nid, x509::{X509NameRef, X509}};
+use parking_lot::Mutex;
+use rquest::tls::TlsInfo;
+use tracing::{debug, info};
+
+fn name_to_string(name: &X509NameRef) -> String {
+    name.entries()
+        .filter_map(|entry| {
+            let key = entry.object().nid().short_name().ok()?;
+            let value = entry.data().as_utf8().ok()?;
+            Some(format!("{}={}", key, value))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn ip_to_string(bytes: &[u8]) -> Option<String> {
+    match bytes.len() {
+        4 => Some(std::net::Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).to_string()),
+        16 => Some(std::net::Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+// Remembers the certificate each upstream host presented so rotations show up in the logs
+#[derive(Default)]
+pub struct UpstreamCertLog {
+    fingerprints: Mutex<HashMap<String, String>>,
+}
+
+impl UpstreamCertLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, host: &str, tls_info: &TlsInfo) {
+        let Some(cert) = tls_info.peer_certificate().and_then(|der| X509::from_der(der).ok()) else {
+            return;
+        };
+        let Ok(digest) = cert.digest(MessageDigest::sha256()) else {
+            return;
+        };
+        let fingerprint = hex(&digest);
+
+        let previous = self.fingerprints.lock().insert(host.to_string(), fingerprint.clone());
+        if previous.as_deref() == Some(fingerprint.as_str()) {
+            return;
+        }
+        if let Some(previous) = previous {
+            info!(component = "TLS", host = %host, "Upstream certificate changed from {} to {}", previous, fingerprint);
+        }
+
+        let sans = cert.subject_alt_names()
+            .map(|names| names.iter()
+                .filter_map(|name| name.dnsname().map(str::to_string)
+                    .or_else(|| name.ipaddress().and_then(ip_to_string)))
+                .collect::<Vec<_>>()
+                .join(", "))
+            .unwrap_or_default();
+        let common_name = cert.subject_name().entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|cn| cn.to_string())
+            .unwrap_or_default();
+
+        debug!(
+            component = "TLS",
+            host = %host,
+            subject = %name_to_string(cert.subject_name()),
+            common_name = %common_name,
+            sans = %sans,
+            issuer = %name_to_string(cert.issuer_name()),
+            not_before = %cert.not_before(),
+            not_after = %cert.not_after(),
+            fingerprint = %fingerprint,
+            "Upstream certificate for {}", host
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt};
+use crate::metrics::MetricsRegistry;
+
+// Reading an upstream body while counting it into proxy_bytes_total{host, direction="received"}
+pub trait ResponseExt {
+    // Buffer the whole body, returned with its length
+    fn bytes_counted(self, metrics: &MetricsRegistry, host: &str)
+        -> impl Future<Output = Result<(Bytes, u64), rquest::Error>> + Send;
+
+    // Stream the body, each chunk is counted as it passes
+    fn stream_counted(self, metrics: Arc<MetricsRegistry>, host: String)
+        -> impl Stream<Item = Result<Bytes, rquest::Error>> + ByteCounter + Send + 'static;
+}
+
+// Body bytes a counted stream has passed on so far
+pub trait ByteCounter {
+    fn byte_count(&self) -> u64;
+}
+
+// Upstream body stream that adds every chunk to the metrics as it is read
+pub struct CountedStream {
+    inner: BoxStream<'static, Result<Bytes, rquest::Error>>,
+    metrics: Arc<MetricsRegistry>,
+    host: String,
+    count: u64,
+}
+
+impl Stream for CountedStream {
+    type Item = Result<Bytes, rquest::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(self.inner.poll_next_unpin(cx));
+        if let Some(Ok(chunk)) = &item {
+            self.metrics.add_bytes_received(&self.host, chunk.len());
+            self.count += chunk.len() as u64;
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl ByteCounter for CountedStream {
+    fn byte_count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl ResponseExt for rquest::Response {
+    async fn bytes_counted(self, metrics: &MetricsRegistry, host: &str) -> Result<(Bytes, u64), rquest::Error> {
+        let body = self.bytes().await?;
+        let len = body.len();
+        metrics.add_bytes_received(host, len);
+        Ok((body, len as u64))
+    }
+
+    fn stream_counted(self, metrics: Arc<MetricsRegistry>, host: String)
+        -> impl Stream<Item = Result<Bytes, rquest::Error>> + ByteCounter + Send + 'static
+    {
+        CountedStream { inner: self.bytes_stream().boxed(), metrics, host, count: 0 }
+    }
+}
@@ -1,29 +1,25 @@
+use std::sync::Arc;
+use std::time::Duration;
+use crate::hooks::{WsDirection, WsHook};
 use crate::types::{Error, ResponseResult, empty};
+use tracing::{debug, error, info};
 use futures_util::{SinkExt, StreamExt};
 use hyper::Response;
 use tokio_tungstenite::{tungstenite::protocol::Role, WebSocketStream};
-use rquest::{Client as RqClient, Message as RqMessage, CloseCode as RqCloseCode};
+use rquest::{Client as RqClient, Message as RqMessage, CloseCode as RqCloseCode, WebSocket as RqWebSocket, WebSocketResponse};
 use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
 
-pub async fn handle_websocket_upgrade<S>(
-    upgraded: S,
-    ws_client: RqClient,
-    url: String,
-    headers: hyper::HeaderMap,
-) -> Result<(), Error>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
-    // Create server WebSocket stream
-    let server_stream = WebSocketStream::from_raw_socket(
-        upgraded,
-        Role::Server,
-        None
-    ).await;
-
+// Open the upstream WebSocket handshake, the caller checks the status before upgrading
+pub async fn connect_websocket_upstream(
+    ws_client: &RqClient,
+    url: &str,
+    headers: &hyper::HeaderMap,
+) -> Result<WebSocketResponse, Error> {
     // Build WebSocket request with rquest client
-    let mut ws_req = ws_client.websocket(&url);
+    let mut ws_req = ws_client.websocket(url);
     
     // Forward headers except those handled by rquest's profile
     for (k, v) in headers.iter() {
@@ -42,8 +38,87 @@ where
         }
     }
 
-    // Send request and convert to websocket
-    let ws_server = ws_req.send().await?.into_websocket().await?;
+    Ok(ws_req.send().await?)
+}
+
+const TEXT_LOG_BYTES: usize = 256;
+const BINARY_LOG_BYTES: usize = 32;
+
+// Logs every relayed frame at debug level, enabled with --log-ws-frames.
+// Only a bounded prefix of each payload is formatted so large frames are never copied.
+#[derive(Debug, Clone, Copy)]
+pub struct WsLogger;
+
+impl WsLogger {
+    fn log(&self, direction: &str, opcode: &str, payload: &[u8], text: bool) {
+        if text {
+            let preview = String::from_utf8_lossy(&payload[..payload.len().min(TEXT_LOG_BYTES)]);
+            debug!(component = "WS", direction, opcode, len = payload.len(), "{}", preview);
+        } else {
+            let preview = payload[..payload.len().min(BINARY_LOG_BYTES)].iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            debug!(component = "WS", direction, opcode, len = payload.len(), "{}", preview);
+        }
+    }
+
+    // Frame from the browser, on its way upstream
+    fn client_frame(&self, msg: &Message) {
+        match msg {
+            Message::Text(text) => self.log("C→S", "Text", text.as_bytes(), true),
+            Message::Binary(data) => self.log("C→S", "Binary", data, false),
+            Message::Ping(data) => self.log("C→S", "Ping", data, false),
+            Message::Pong(data) => self.log("C→S", "Pong", data, false),
+            Message::Close(frame) => {
+                let code = frame.as_ref().map(|f| u16::from(f.code));
+                debug!(component = "WS", direction = "C→S", opcode = "Close", code, "Close");
+            }
+            _ => {}
+        }
+    }
+
+    // Frame from the upstream server, on its way to the browser
+    fn server_frame(&self, msg: &RqMessage) {
+        match msg {
+            RqMessage::Text(text) => self.log("S→C", "Text", text.as_bytes(), true),
+            RqMessage::Binary(data) => self.log("S→C", "Binary", data, false),
+            RqMessage::Ping(data) => self.log("S→C", "Ping", data, false),
+            RqMessage::Pong(data) => self.log("S→C", "Pong", data, false),
+            RqMessage::Close { code, .. } => {
+                debug!(component = "WS", direction = "S→C", opcode = "Close", code = u16::from(*code), "Close");
+            }
+        }
+    }
+}
+
+fn apply_hooks(hooks: &[Box<dyn WsHook + Send + Sync>], direction: WsDirection, msg: &mut Message) -> Result<(), Error> {
+    for hook in hooks {
+        hook.on_message(direction, msg)?;
+    }
+    Ok(())
+}
+
+// Relay frames between the client and upstream. When `shutdown` is cancelled both sides get a
+// 1001 Going Away close frame and the connection is dropped if they have not closed within `grace`.
+// Hooks see every message after conversion and an error from one of them ends the tunnel.
+pub async fn handle_websocket_upgrade<S>(
+    upgraded: S,
+    ws_server: RqWebSocket,
+    shutdown: CancellationToken,
+    grace: Duration,
+    logger: Option<WsLogger>,
+    hooks: Arc<Vec<Box<dyn WsHook + Send + Sync>>>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Create server WebSocket stream
+    let server_stream = WebSocketStream::from_raw_socket(
+        upgraded,
+        Role::Server,
+        None
+    ).await;
 
     // Split streams for bidirectional communication
     let (server_write, server_read) = server_stream.split();
@@ -53,8 +128,29 @@ where
     let client_to_server = async {
         let mut client_read = client_read;
         let mut server_write = server_write;
-        while let Some(msg) = client_read.next().await {
+        let mut closing = false;
+        loop {
+            let msg = tokio::select! {
+                msg = client_read.next() => msg,
+                _ = shutdown.cancelled(), if !closing => {
+                    closing = true;
+                    let _ = server_write.send(Message::Close(Some(
+                        CloseFrame {
+                            code: CloseCode::Away,
+                            reason: "Proxy shutting down".into(),
+                        }
+                    ))).await;
+                    continue;
+                }
+            };
+            let Some(msg) = msg else {
+                break;
+            };
             if let Ok(msg) = msg {
+                if let Some(logger) = &logger {
+                    logger.server_frame(&msg);
+                }
+
                 // Convert rquest::Message to tungstenite::Message
                 let msg = match msg {
                     RqMessage::Text(text) => Message::Text(text),
@@ -62,33 +158,24 @@ where
                     RqMessage::Ping(data) => Message::Ping(data),
                     RqMessage::Pong(data) => Message::Pong(data),
                     RqMessage::Close { code, reason } => {
-                        let close_code = match code {
-                            RqCloseCode::Normal => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Normal,
-                            RqCloseCode::Away => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Away,
-                            RqCloseCode::Protocol => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Protocol,
-                            RqCloseCode::Unsupported => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Unsupported,
-                            RqCloseCode::Status => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Status,
-                            RqCloseCode::Abnormal => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Abnormal,
-                            RqCloseCode::Invalid => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Invalid,
-                            RqCloseCode::Policy => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Policy,
-                            RqCloseCode::Size => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Size,
-                            RqCloseCode::Extension => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Extension,
-                            RqCloseCode::Error => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Error,
-                            RqCloseCode::Restart => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Restart,
-                            RqCloseCode::Again => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Again,
-                            _ => tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Normal,
-                        };
+                        // Go through the raw code so application codes (3000-4999) survive the relay
+                        let close_code = CloseCode::from(u16::from(code));
                         Message::Close(Some(
-                            tokio_tungstenite::tungstenite::protocol::CloseFrame {
+                            CloseFrame {
                                 code: close_code,
                                 reason: reason.unwrap_or_default().into(),
                             }
                         ))
                     }
                 };
+                let mut msg = msg;
+                if let Err(e) = apply_hooks(&hooks, WsDirection::ServerToClient, &mut msg) {
+                    error!(component = "WS", "WebSocket hook closed the tunnel: {}", e);
+                    break;
+                }
 
                 if let Err(e) = server_write.send(msg).await {
-                    eprintln!("[ERROR] WebSocket send failed: {}", e);
+                    error!(component = "WS", "WebSocket send failed: {}", e);
                     break;
                 }
             }
@@ -99,8 +186,32 @@ where
     let server_to_client = async {
         let mut server_read = server_read;
         let mut client_write = client_write;
-        while let Some(msg) = server_read.next().await {
+        let mut closing = false;
+        loop {
+            let msg = tokio::select! {
+                msg = server_read.next() => msg,
+                _ = shutdown.cancelled(), if !closing => {
+                    closing = true;
+                    let _ = client_write.send(RqMessage::Close {
+                        code: RqCloseCode::Away,
+                        reason: Some("Proxy shutting down".to_string()),
+                    }).await;
+                    continue;
+                }
+            };
+            let Some(msg) = msg else {
+                break;
+            };
             if let Ok(msg) = msg {
+                if let Some(logger) = &logger {
+                    logger.client_frame(&msg);
+                }
+                let mut msg = msg;
+                if let Err(e) = apply_hooks(&hooks, WsDirection::ClientToServer, &mut msg) {
+                    error!(component = "WS", "WebSocket hook closed the tunnel: {}", e);
+                    break;
+                }
+
                 // Convert tungstenite::Message to rquest::Message
                 let msg = match msg {
                     Message::Text(text) => RqMessage::Text(text),
@@ -109,22 +220,7 @@ where
                     Message::Pong(data) => RqMessage::Pong(data),
                     Message::Close(frame) => {
                         let (code, reason) = frame.map(|f| {
-                            let code = match f.code {
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Normal => RqCloseCode::Normal,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Away => RqCloseCode::Away,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Protocol => RqCloseCode::Protocol,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Unsupported => RqCloseCode::Unsupported,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Status => RqCloseCode::Status,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Abnormal => RqCloseCode::Abnormal,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Invalid => RqCloseCode::Invalid,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Policy => RqCloseCode::Policy,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Size => RqCloseCode::Size,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Extension => RqCloseCode::Extension,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Error => RqCloseCode::Error,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Restart => RqCloseCode::Restart,
-                                tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Again => RqCloseCode::Again,
-                                _ => RqCloseCode::Normal,
-                            };
+                            let code = RqCloseCode::from(u16::from(f.code));
                             (code, Some(f.reason.to_string()))
                         }).unwrap_or((RqCloseCode::Normal, None));
                         RqMessage::Close { code, reason }
@@ -133,7 +229,7 @@ where
                 };
 
                 if let Err(e) = client_write.send(msg).await {
-                    eprintln!("[ERROR] WebSocket send failed: {}", e);
+                    error!(component = "WS", "WebSocket send failed: {}", e);
                     break;
                 }
             }
@@ -141,9 +237,22 @@ where
     };
 
     // Run both directions concurrently
+    let relay = async {
+        tokio::select! {
+            _ = client_to_server => {},
+            _ = server_to_client => {},
+        }
+    };
+
+    // Once shutdown starts the close handshakes get `grace` to finish before the connection is dropped
+    let grace_expired = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(grace).await;
+    };
+
     tokio::select! {
-        _ = client_to_server => {},
-        _ = server_to_client => {},
+        _ = relay => {},
+        _ = grace_expired => info!(component = "WS", "WebSocket did not close within {:?}, closing it", grace),
     }
 
     Ok(())
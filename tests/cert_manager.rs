@@ -0,0 +1,161 @@
+mod common;
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use boring_proxy::{
+    cert_manager::{CertManager, KeyType},
+    metrics::MetricsRegistry,
+};
+use common::test_config;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+fn cert_manager(name: &str, leaf_key_type: KeyType, cert_key_reuse: bool) -> CertManager {
+    let config = boring_proxy::config::Config {
+        leaf_key_type,
+        cert_key_reuse,
+        ..test_config(name)
+    };
+    CertManager::new(&config, Arc::new(MetricsRegistry::new().unwrap())).unwrap()
+}
+
+// SAN entries of the leaf certificate issued for `host`, as IP addresses and DNS names
+async fn leaf_sans(manager: &CertManager, host: &str) -> (Vec<IpAddr>, Vec<String>) {
+    let (chain, _) = manager.get_or_create_cert(host).await.unwrap();
+    let (_, cert) = X509Certificate::from_der(&chain[0].0).unwrap();
+    let san = cert.subject_alternative_name().unwrap().expect("no subjectAltName");
+
+    let (mut ips, mut names) = (Vec::new(), Vec::new());
+    for name in &san.value.general_names {
+        match name {
+            GeneralName::IPAddress(bytes) => ips.push(match bytes.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).unwrap()),
+                _ => IpAddr::from(<[u8; 16]>::try_from(*bytes).unwrap()),
+            }),
+            GeneralName::DNSName(dns) => names.push(dns.to_string()),
+            other => panic!("unexpected SAN {:?}", other),
+        }
+    }
+    (ips, names)
+}
+
+fn cached_domains(manager: &CertManager) -> Vec<String> {
+    manager.cached_certs().into_iter().map(|(domain, _)| domain).collect()
+}
+
+#[tokio::test]
+async fn ip_host_gets_ip_san() {
+    let manager = cert_manager("ip-san", KeyType::EcdsaP256, false);
+    let (ips, names) = leaf_sans(&manager, "192.168.1.1").await;
+    assert_eq!(ips, vec!["192.168.1.1".parse::<IpAddr>().unwrap()]);
+    assert!(names.is_empty(), "{:?}", names);
+}
+
+#[tokio::test]
+async fn loopback_ipv4_is_cached_under_the_address() {
+    let manager = cert_manager("loopback-v4", KeyType::EcdsaP256, false);
+    let (ips, names) = leaf_sans(&manager, "127.0.0.1").await;
+    assert_eq!(ips, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    assert!(names.is_empty(), "{:?}", names);
+    assert_eq!(cached_domains(&manager), vec!["127.0.0.1"]);
+}
+
+#[tokio::test]
+async fn bracketed_ipv6_is_cached_under_the_bare_address() {
+    let manager = cert_manager("loopback-v6", KeyType::EcdsaP256, false);
+    let (ips, names) = leaf_sans(&manager, "[::1]").await;
+    assert_eq!(ips, vec!["::1".parse::<IpAddr>().unwrap()]);
+    assert!(names.is_empty(), "{:?}", names);
+    assert_eq!(cached_domains(&manager), vec!["::1"]);
+
+    // The bare form used elsewhere hits the same entry
+    manager.get_or_create_cert("::1").await.unwrap();
+    assert_eq!(cached_domains(&manager), vec!["::1"]);
+}
+
+#[tokio::test]
+async fn cache_gauges_follow_inserts_and_evictions() {
+    let metrics = Arc::new(MetricsRegistry::new().unwrap());
+    let manager = CertManager::new(&test_config("cache-gauges"), Arc::clone(&metrics)).unwrap();
+    let gauges = || {
+        let text = String::from_utf8(metrics.encode().unwrap()).unwrap();
+        let value = |name: &str| text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse::<i64>().ok())
+            .unwrap();
+        (value("cert_cache_entries"), value("cert_cache_bytes"))
+    };
+
+    manager.get_or_create_cert("one.test").await.unwrap();
+    manager.get_or_create_cert("two.test").await.unwrap();
+    let (entries, bytes) = gauges();
+    assert_eq!(entries, 2);
+    assert!(bytes > 0);
+
+    assert!(manager.evict_cert("one.test"));
+    let (entries, remaining) = gauges();
+    assert_eq!(entries, 1);
+    assert!(remaining > 0 && remaining < bytes);
+
+    assert!(manager.evict_cert("two.test"));
+    assert_eq!(gauges(), (0, 0));
+}
+
+// Every CA is within a century of expiry, so these exercise the renewal path at once
+fn renewing_config(name: &str) -> boring_proxy::config::Config {
+    boring_proxy::config::Config { ca_renew_days: 36500, ..test_config(name) }
+}
+
+#[tokio::test]
+async fn generated_ca_is_renewed() {
+    let config = renewing_config("renew-generated");
+    let manager = CertManager::new(&config, Arc::new(MetricsRegistry::new().unwrap())).unwrap();
+    let before = std::fs::read(&config.ca_cert_path).unwrap();
+
+    assert!(manager.check_and_renew_ca().unwrap());
+    assert_ne!(std::fs::read(&config.ca_cert_path).unwrap(), before);
+}
+
+#[tokio::test]
+async fn provided_ca_is_never_overwritten() {
+    let config = renewing_config("renew-provided");
+    let mut params = rcgen::CertificateParams::new(Vec::new());
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    params.key_usages = vec![rcgen::KeyUsagePurpose::KeyCertSign, rcgen::KeyUsagePurpose::CrlSign];
+    params.distinguished_name.push(rcgen::DnType::CommonName, "Corp Root CA");
+    let ca = rcgen::Certificate::from_params(params).unwrap();
+    let ca_pem = ca.serialize_pem().unwrap();
+    std::fs::write(&config.ca_cert_path, &ca_pem).unwrap();
+    std::fs::write(&config.ca_key_path, ca.serialize_private_key_pem()).unwrap();
+
+    let manager = CertManager::new(&config, Arc::new(MetricsRegistry::new().unwrap())).unwrap();
+    assert!(!manager.check_and_renew_ca().unwrap());
+    assert_eq!(std::fs::read_to_string(&config.ca_cert_path).unwrap(), ca_pem);
+}
+
+// Time to issue `count` certificates for hosts that don't share one
+async fn issue(manager: &CertManager, count: usize) -> Duration {
+    let start = Instant::now();
+    for i in 0..count {
+        manager.get_or_create_cert(&format!("host{}.test", i)).await.unwrap();
+    }
+    start.elapsed()
+}
+
+// Timing comparison for cert_key_reuse, run with
+// `cargo test --release --test cert_manager -- --ignored --nocapture`
+#[tokio::test]
+#[ignore]
+async fn cert_key_reuse_skips_key_generation() {
+    const CERTS: usize = 20;
+    for key_type in [KeyType::EcdsaP256, KeyType::Rsa2048, KeyType::Rsa4096] {
+        let fresh = issue(&cert_manager("keygen-fresh", key_type, false), CERTS).await;
+        let reused = issue(&cert_manager("keygen-reused", key_type, true), CERTS).await;
+        println!(
+            "{:?}: {:?} per certificate with a new key, {:?} with cert_key_reuse",
+            key_type, fresh / CERTS as u32, reused / CERTS as u32
+        );
+        if key_type != KeyType::EcdsaP256 {
+            assert!(reused < fresh, "{:?}: reuse took {:?}, fresh keys {:?}", key_type, reused, fresh);
+        }
+    }
+}
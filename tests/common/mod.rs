@@ -0,0 +1,124 @@
+// Helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use hyper::{body::Incoming, service::service_fn, Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use rustls::ServerConfig;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use boring_proxy::{
+    cert_manager::{CertManager, KeyType},
+    config::Config,
+    metrics::MetricsRegistry,
+    proxy::Proxy,
+    types::{full, ResponseBody},
+};
+
+// Config for a proxy on a random loopback port, with its own CA in a fresh directory. EC keys keep
+// certificate generation fast.
+pub fn test_config(name: &str) -> Config {
+    let dir = std::env::temp_dir().join(format!("boring-proxy-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    Config {
+        bind_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+        ca_cert_path: dir.join("ca.crt"),
+        ca_key_path: dir.join("ca.key"),
+        ca_key_type: KeyType::EcdsaP256,
+        leaf_key_type: KeyType::EcdsaP256,
+        cookie_dir: dir.join("cookies"),
+        ..Config::default()
+    }
+}
+
+// Start the proxy on a random port, its connections are served the same way main serves them
+pub async fn start_proxy(config: Config) -> (SocketAddr, Arc<Proxy>) {
+    serve_proxy(Proxy::new(&config, Vec::new()).await.unwrap()).await
+}
+
+// Serve an already built proxy, e.g. one with hooks added, on a random loopback port
+pub async fn serve_proxy(proxy: Proxy) -> (SocketAddr, Arc<Proxy>) {
+    let proxy = Arc::new(proxy);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let accept_proxy = Arc::clone(&proxy);
+    tokio::spawn(async move {
+        while let Ok((stream, client_addr)) = listener.accept().await {
+            accept_proxy.serve_connection(stream, client_addr);
+        }
+    });
+    (addr, proxy)
+}
+
+// Plain HTTP/1.1 upstream answering every request with `handler`
+pub async fn start_upstream<F, Fut>(handler: F) -> SocketAddr
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<ResponseBody>> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let response = handler(req);
+                    async move { Ok::<_, std::convert::Infallible>(response.await) }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    addr
+}
+
+// TLS origin offering `alpn` protocols, answering with the HTTP version it was spoken to in.
+// `name` keeps its CA apart from other origins started by the same test binary.
+pub async fn start_tls_upstream(name: &str, alpn: &[&[u8]]) -> SocketAddr {
+    let certs = CertManager::new(&test_config(name), Arc::new(MetricsRegistry::new().unwrap())).unwrap();
+    let (chain, key) = certs.get_or_create_cert("127.0.0.1").await.unwrap();
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(tls) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let service = service_fn(|req| async move {
+                    Ok::<_, std::convert::Infallible>(Response::new(full(format!("{:?}", req.version()))))
+                });
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(tls), service)
+                    .await;
+            });
+        }
+    });
+    addr
+}
+
+// Send a raw HTTP/1.1 request to the proxy and return everything it answered until it closed
+pub async fn raw_request(proxy: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
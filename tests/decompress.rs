@@ -0,0 +1,56 @@
+mod common;
+
+use std::io::{Read, Write};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use hyper::Response;
+use boring_proxy::types::full;
+use common::{start_proxy, start_upstream, test_config};
+
+const BODY: &str = "hello from a gzip origin, hello from a gzip origin";
+
+async fn gzip_upstream() -> std::net::SocketAddr {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(BODY.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    start_upstream(move |_req| {
+        let compressed = compressed.clone();
+        async move {
+            Response::builder()
+                .header(hyper::header::CONTENT_ENCODING, "gzip")
+                .header(hyper::header::CONTENT_TYPE, "text/plain")
+                .body(full(compressed))
+                .unwrap()
+        }
+    }).await
+}
+
+// Fetch through the proxy with a client that does not decode bodies itself
+async fn fetch(decompress: bool, name: &str) -> reqwest::Response {
+    let upstream = gzip_upstream().await;
+    let mut config = test_config(name);
+    config.rquest.decompress = decompress;
+    let (proxy, _proxy) = start_proxy(config).await;
+
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::http(format!("http://{}", proxy)).unwrap())
+        .build()
+        .unwrap();
+    client.get(format!("http://{}/", upstream)).send().await.unwrap()
+}
+
+#[tokio::test]
+async fn compressed_body_passes_through_by_default() {
+    let response = fetch(false, "gzip-passthrough").await;
+    assert_eq!(response.headers()[hyper::header::CONTENT_ENCODING], "gzip");
+
+    let mut body = String::new();
+    GzDecoder::new(&response.bytes().await.unwrap()[..]).read_to_string(&mut body).unwrap();
+    assert_eq!(body, BODY);
+}
+
+#[tokio::test]
+async fn decompress_decodes_the_body() {
+    let response = fetch(true, "gzip-decoded").await;
+    assert!(response.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+    assert_eq!(response.text().await.unwrap(), BODY);
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use hyper::Response;
+use boring_proxy::{config::Config, types::full};
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+const LIMIT: usize = 1024;
+
+// Two identical GETs, the second sent while the first is still waiting on the slow upstream.
+// Returns both responses and how many requests the upstream saw.
+async fn concurrent_pair(name: &str, body_len: usize) -> (String, String, usize) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let upstream_hits = Arc::clone(&hits);
+    let upstream = start_upstream(move |_req| {
+        upstream_hits.fetch_add(1, Ordering::SeqCst);
+        async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Response::new(full(vec![b'x'; body_len]))
+        }
+    }).await;
+    let config = Config {
+        dedup: true,
+        max_response_buffer_bytes: Some(LIMIT),
+        ..test_config(name)
+    };
+    let (proxy, _proxy) = start_proxy(config).await;
+
+    let request = format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n");
+    let leader = raw_request(proxy, &request);
+    let follower = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        raw_request(proxy, &request).await
+    };
+    let (leader, follower) = tokio::join!(leader, follower);
+    (leader, follower, hits.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn small_responses_are_shared() {
+    let (leader, follower, hits) = concurrent_pair("dedup-small", LIMIT / 2).await;
+    assert!(leader.starts_with("HTTP/1.1 200"), "{}", leader);
+    assert!(follower.starts_with("HTTP/1.1 200"), "{}", follower);
+    assert!(follower.ends_with(&"x".repeat(LIMIT / 2)), "{}", follower);
+    assert_eq!(hits, 1);
+}
+
+#[tokio::test]
+async fn responses_over_the_buffer_limit_are_not_shared() {
+    let (leader, follower, hits) = concurrent_pair("dedup-large", LIMIT * 4).await;
+    assert!(leader.starts_with("HTTP/1.1 200"), "{}", leader);
+    assert!(leader.ends_with(&"x".repeat(LIMIT * 4)), "{}", leader);
+    assert!(follower.starts_with("HTTP/1.1 503"), "{}", follower);
+    assert_eq!(hits, 1);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::{http::{request, response}, Response};
+use boring_proxy::{
+    hooks::{RequestHook, ResponseHook},
+    proxy::Proxy,
+    types::{full, Error},
+};
+use common::{raw_request, serve_proxy, start_upstream, test_config};
+
+// Uppercases the request body and marks the request
+struct ShoutHook;
+
+impl RequestHook for ShoutHook {
+    fn modify_request(&self, req: &mut request::Parts, body: &mut Bytes) -> Result<(), Error> {
+        *body = Bytes::from(body.to_ascii_uppercase());
+        req.headers.insert("x-hooked", "request".parse()?);
+        Ok(())
+    }
+}
+
+struct TagHook;
+
+impl ResponseHook for TagHook {
+    fn modify_response(&self, res: &mut response::Parts, _body: &mut Bytes) -> Result<(), Error> {
+        res.headers.insert("x-hooked", "response".parse()?);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn hooks_rewrite_the_upstream_request_and_response() {
+    // Echoes the body it received and the marker the request hook added
+    let upstream = start_upstream(|req| async move {
+        let marker = req.headers().get("x-hooked").map(|v| v.to_str().unwrap().to_string()).unwrap_or_default();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        Response::new(full(format!("{} {}", marker, String::from_utf8_lossy(&body))))
+    }).await;
+    let proxy = Proxy::new(&test_config("hooks"), Vec::new()).await.unwrap()
+        .with_request_hook(ShoutHook)
+        .with_response_hook(TagHook);
+    let (proxy, _proxy) = serve_proxy(proxy).await;
+
+    let response = raw_request(
+        proxy,
+        &format!("POST http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nContent-Length: 11\r\nConnection: close\r\n\r\nhello world"),
+    ).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.to_lowercase().contains("x-hooked: response\r\n"), "{}", response);
+    assert!(response.ends_with("request HELLO WORLD"), "{}", response);
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use common::{raw_request, start_proxy, start_tls_upstream, test_config};
+
+// Version the origin reports for a GET through a fresh proxy
+async fn upstream_version(name: &str, alpn: &[&[u8]]) -> String {
+    let upstream = start_tls_upstream(&format!("{}-origin", name), alpn).await;
+    let (proxy, _proxy) = start_proxy(test_config(name)).await;
+
+    let response = raw_request(
+        proxy,
+        &format!("GET https://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n"),
+    ).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    response.rsplit("\r\n").next().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn upstream_negotiates_http2() {
+    assert_eq!(upstream_version("h2-offered", &[b"h2", b"http/1.1"]).await, "HTTP/2.0");
+}
+
+// Control for the test above, an origin without h2 is spoken to in HTTP/1.1
+#[tokio::test]
+async fn upstream_without_h2_gets_http1() {
+    assert_eq!(upstream_version("h1-only", &[b"http/1.1"]).await, "HTTP/1.1");
+}
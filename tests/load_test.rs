@@ -0,0 +1,92 @@
+// Load test for the proxy, run with `cargo test --test load_test -- --ignored --nocapture`
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use goose::prelude::*;
+use hyper::Response;
+use boring_proxy::types::full;
+use common::{start_proxy, start_upstream, test_config};
+
+const USERS: usize = 100;
+const REQUESTS_PER_USER: usize = 1000;
+const MAX_P99_MS: usize = 500;
+const MAX_ERROR_RATE: f64 = 0.001;
+
+// Transactions are plain functions, so the proxy address is handed to them through a static
+static PROXY_URL: OnceLock<String> = OnceLock::new();
+
+// Send every request of this user through the proxy
+async fn use_proxy(user: &mut GooseUser) -> TransactionResult {
+    let proxy_url = PROXY_URL.get().expect("proxy not started");
+    let builder = reqwest::Client::builder().proxy(reqwest::Proxy::http(proxy_url)?);
+    user.set_client_builder(builder).await?;
+    Ok(())
+}
+
+async fn fetch(user: &mut GooseUser) -> TransactionResult {
+    let _goose = user.get("/").await?;
+    Ok(())
+}
+
+// Smallest response time (ms) at or below which `fraction` of the requests completed
+fn percentile(times: &BTreeMap<usize, usize>, total: usize, fraction: f64) -> usize {
+    let target = ((total as f64) * fraction).ceil() as usize;
+    let mut seen = 0;
+    for (time, count) in times {
+        seen += count;
+        if seen >= target {
+            return *time;
+        }
+    }
+    0
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn load_test() -> Result<(), GooseError> {
+    let upstream = start_upstream(|_req| async { Response::new(full("ok")) }).await;
+    let (proxy, _proxy) = start_proxy(test_config("load-test")).await;
+    PROXY_URL.set(format!("http://{}", proxy)).unwrap();
+
+    // initialize() would parse the test harness arguments, so start from the defaults
+    let metrics = GooseAttack::initialize_with_config(GooseConfiguration::default())?
+        .register_scenario(scenario!("ProxiedUser")
+            .register_transaction(transaction!(use_proxy).set_on_start())
+            .register_transaction(transaction!(fetch)))
+        .set_default(GooseDefault::Host, format!("http://{}", upstream).as_str())?
+        .set_default(GooseDefault::Users, USERS)?
+        .set_default(GooseDefault::HatchRate, USERS.to_string().as_str())?
+        .set_default(GooseDefault::Iterations, REQUESTS_PER_USER)?
+        .set_default(GooseDefault::NoResetMetrics, true)?
+        .set_default(GooseDefault::Quiet, 1)?
+        .execute()
+        .await?;
+
+    let mut times = BTreeMap::new();
+    let (mut total, mut failed) = (0, 0);
+    for aggregate in metrics.requests.values() {
+        for (time, count) in &aggregate.raw_data.times {
+            *times.entry(*time).or_insert(0) += count;
+        }
+        total += aggregate.success_count + aggregate.fail_count;
+        failed += aggregate.fail_count;
+    }
+    assert!(total > 0, "no requests were made");
+
+    let p50 = percentile(&times, total, 0.50);
+    let p95 = percentile(&times, total, 0.95);
+    let p99 = percentile(&times, total, 0.99);
+    let error_rate = failed as f64 / total as f64;
+    let throughput = total as f64 / metrics.duration.max(1) as f64;
+
+    println!();
+    println!("Load test: {} users x {} requests through {}", USERS, REQUESTS_PER_USER, proxy);
+    println!("  requests:   {} ({} failed, {:.3}% error rate)", total, failed, error_rate * 100.0);
+    println!("  latency:    p50 {} ms, p95 {} ms, p99 {} ms", p50, p95, p99);
+    println!("  throughput: {:.0} requests/s over {} s", throughput, metrics.duration);
+
+    assert!(p99 < MAX_P99_MS, "p99 latency {} ms is not below {} ms", p99, MAX_P99_MS);
+    assert!(error_rate < MAX_ERROR_RATE, "error rate {:.3}% is not below {:.1}%", error_rate * 100.0, MAX_ERROR_RATE * 100.0);
+    Ok(())
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use hyper::Response;
+use boring_proxy::types::{full, MAX_HEADERS};
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+// GET through the proxy with `count` headers in total, Host included
+fn request_with_headers(upstream: std::net::SocketAddr, count: usize) -> String {
+    let mut request = format!("GET http://{0}/ HTTP/1.1\r\nHost: {0}\r\nConnection: close\r\n", upstream);
+    for i in 0..count - 2 {
+        request.push_str(&format!("X-Test-{}: {}\r\n", i, i));
+    }
+    request.push_str("\r\n");
+    request
+}
+
+#[tokio::test]
+async fn too_many_headers_get_431() {
+    let upstream = start_upstream(|_req| async { Response::new(full("ok")) }).await;
+    let (proxy, _proxy) = start_proxy(test_config("max-headers")).await;
+
+    let response = raw_request(proxy, &request_with_headers(upstream, MAX_HEADERS)).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{} headers should be accepted: {}", MAX_HEADERS, response);
+
+    let response = raw_request(proxy, &request_with_headers(upstream, MAX_HEADERS + 1)).await;
+    assert!(response.starts_with("HTTP/1.1 431"), "{} headers should get 431: {}", MAX_HEADERS + 1, response);
+}
+
+#[tokio::test]
+async fn header_limit_is_configurable() {
+    let upstream = start_upstream(|_req| async { Response::new(full("ok")) }).await;
+    let (proxy, _proxy) = start_proxy(boring_proxy::config::Config {
+        max_request_headers: 10,
+        ..test_config("max-headers-configured")
+    }).await;
+
+    let response = raw_request(proxy, &request_with_headers(upstream, 10)).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let response = raw_request(proxy, &request_with_headers(upstream, 11)).await;
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use hyper::Response;
+use boring_proxy::types::empty;
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+// Upstream that reports the method it received in a header, so HEAD responses can carry it too
+async fn method_echo() -> std::net::SocketAddr {
+    start_upstream(|req| async move {
+        Response::builder()
+            .header("x-method", req.method().as_str())
+            .body(empty())
+            .unwrap()
+    }).await
+}
+
+#[tokio::test]
+async fn head_is_forwarded_as_head() {
+    let upstream = method_echo().await;
+    let (proxy, _proxy) = start_proxy(test_config("head")).await;
+
+    let response = raw_request(proxy, &format!("HEAD http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.to_lowercase().contains("x-method: head\r\n"), "{}", response);
+}
+
+#[tokio::test]
+async fn unknown_method_gets_405() {
+    let upstream = method_echo().await;
+    let (proxy, _proxy) = start_proxy(test_config("propfind")).await;
+
+    let response = raw_request(proxy, &format!("PROPFIND http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+    assert!(response.ends_with("Method PROPFIND is not supported"), "{}", response);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use hyper::Response;
+use tokio::net::TcpListener;
+use boring_proxy::{metrics, types::full};
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+const BODY_LEN: usize = 12345;
+
+// Bytes received from an upstream body show up under proxy_bytes_total on the /metrics endpoint
+#[tokio::test]
+async fn received_bytes_are_scraped() {
+    let upstream = start_upstream(|_req| async { Response::new(full(vec![b'x'; BODY_LEN])) }).await;
+    let (proxy, proxy_handle) = start_proxy(test_config("metrics-bytes")).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let metrics_addr = listener.local_addr().unwrap();
+    tokio::spawn(metrics::serve_listener(listener, proxy_handle));
+
+    let response = raw_request(
+        proxy,
+        &format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n"),
+    ).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with(&"x".repeat(BODY_LEN)));
+
+    let scrape = raw_request(metrics_addr, "GET /metrics HTTP/1.1\r\nHost: metrics\r\nConnection: close\r\n\r\n").await;
+    let received = scrape.lines()
+        .find(|line| line.starts_with("proxy_bytes_total{") && line.contains(r#"direction="received""#))
+        .unwrap_or_else(|| panic!("no received bytes in {}", scrape));
+    assert!(received.contains(&format!(r#"host="{}""#, upstream.ip())), "{}", received);
+    assert!(received.ends_with(&format!(" {}", BODY_LEN)), "{}", received);
+}
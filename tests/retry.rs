@@ -0,0 +1,47 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use hyper::{Response, StatusCode};
+use boring_proxy::{config::{Config, RetryPolicy}, types::full};
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+// GET through a proxy with three attempts against an upstream that always answers 503.
+// Returns the response and how many requests the upstream saw.
+async fn get_unavailable(name: &str, retryable_statuses: Vec<u16>) -> (String, usize) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let upstream_hits = Arc::clone(&hits);
+    let upstream = start_upstream(move |_req| {
+        upstream_hits.fetch_add(1, Ordering::SeqCst);
+        async move {
+            let mut response = Response::new(full("busy"));
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response
+        }
+    }).await;
+    let config = Config {
+        retry: RetryPolicy { max_attempts: 3, initial_delay_ms: 10, retryable_statuses, ..RetryPolicy::default() },
+        ..test_config(name)
+    };
+    let (proxy, _proxy) = start_proxy(config).await;
+
+    let response = raw_request(
+        proxy,
+        &format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n"),
+    ).await;
+    (response, hits.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn error_statuses_are_not_retried_by_default() {
+    let (response, hits) = get_unavailable("retry-default", RetryPolicy::default().retryable_statuses).await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+    assert_eq!(hits, 1);
+}
+
+#[tokio::test]
+async fn configured_statuses_are_retried() {
+    let (response, hits) = get_unavailable("retry-statuses", vec![503]).await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+    assert_eq!(hits, 3);
+}
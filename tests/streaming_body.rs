@@ -0,0 +1,59 @@
+mod common;
+
+use std::time::Duration;
+use http_body_util::BodyExt;
+use hyper::{body::Incoming, Request, Response};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use boring_proxy::types::full;
+use common::{start_proxy, start_upstream, test_config};
+
+const BODY_LEN: usize = 10 * 1024 * 1024;
+const CHUNK_LEN: usize = 64 * 1024;
+
+// The upstream gets the start of a 10 MB upload while the client is still holding back the rest,
+// so the proxy forwards the body as it arrives instead of collecting it first
+#[tokio::test]
+async fn request_body_is_streamed_upstream() {
+    let (first_bytes_tx, mut first_bytes) = mpsc::unbounded_channel();
+    let upstream = start_upstream(move |req: Request<Incoming>| {
+        let first_bytes_tx = first_bytes_tx.clone();
+        async move {
+            let mut body = req.into_body();
+            let mut received = 0;
+            while let Some(frame) = body.frame().await {
+                if let Ok(data) = frame.unwrap().into_data() {
+                    if received == 0 {
+                        let _ = first_bytes_tx.send(());
+                    }
+                    received += data.len();
+                }
+            }
+            Response::new(full(received.to_string()))
+        }
+    }).await;
+    let (proxy, _proxy) = start_proxy(test_config("streaming-body")).await;
+
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let head = format!(
+        "POST http://{upstream}/upload HTTP/1.1\r\nHost: {upstream}\r\nContent-Length: {BODY_LEN}\r\nConnection: close\r\n\r\n"
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let chunk = vec![b'x'; CHUNK_LEN];
+    stream.write_all(&chunk).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), first_bytes.recv())
+        .await
+        .expect("upstream saw nothing before the whole body was sent, the proxy buffered it")
+        .unwrap();
+
+    for _ in 1..BODY_LEN / CHUNK_LEN {
+        stream.write_all(&chunk).await.unwrap();
+    }
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with(&BODY_LEN.to_string()), "{}", response);
+}
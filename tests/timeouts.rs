@@ -0,0 +1,65 @@
+mod common;
+
+use std::time::{Duration, Instant};
+use hyper::Response;
+use boring_proxy::{config::{Config, RquestConfig, TimeoutOverride}, types::full};
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+const UPSTREAM_DELAY: Duration = Duration::from_secs(3);
+
+fn with_override(name: &str, domain: &str) -> Config {
+    Config {
+        timeout_overrides: vec![TimeoutOverride {
+            domain: domain.to_string(),
+            request_timeout_secs: 1,
+            connect_timeout_secs: 1,
+        }],
+        ..test_config(name)
+    }
+}
+
+async fn slow_upstream() -> std::net::SocketAddr {
+    start_upstream(|_req| async {
+        tokio::time::sleep(UPSTREAM_DELAY).await;
+        Response::new(full("slow"))
+    }).await
+}
+
+#[tokio::test]
+async fn domain_timeout_trips_on_a_slow_upstream() {
+    let upstream = slow_upstream().await;
+    let (proxy, _proxy) = start_proxy(with_override("timeout-hit", "127.0.0.1")).await;
+
+    let start = Instant::now();
+    let response = raw_request(proxy, &format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+    assert!(start.elapsed() < UPSTREAM_DELAY, "took {:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn other_domains_keep_the_default_timeout() {
+    let upstream = slow_upstream().await;
+    let (proxy, _proxy) = start_proxy(with_override("timeout-miss", "*.example.com")).await;
+
+    let response = raw_request(proxy, &format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("slow"), "{}", response);
+}
+
+// The global [rquest] timeout applies to every host without an override. Waits on a real timeout, run with
+// `cargo test --test timeouts -- --ignored`
+#[tokio::test]
+#[ignore]
+async fn global_timeout_trips_on_a_slow_upstream() {
+    let upstream = slow_upstream().await;
+    let config = Config {
+        rquest: RquestConfig { timeout_secs: 1, ..RquestConfig::default() },
+        ..test_config("timeout-global")
+    };
+    let (proxy, _proxy) = start_proxy(config).await;
+
+    let start = Instant::now();
+    let response = raw_request(proxy, &format!("GET http://{upstream}/ HTTP/1.1\r\nHost: {upstream}\r\nConnection: close\r\n\r\n")).await;
+    assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+    assert!(start.elapsed() < UPSTREAM_DELAY, "took {:?}", start.elapsed());
+}
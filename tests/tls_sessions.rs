@@ -0,0 +1,43 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use boring_proxy::config::Config;
+use boring_proxy::session_manager::{SessionConfig, SessionManager};
+use common::start_tls_upstream;
+
+const REQUESTS: u32 = 50;
+
+// Time for REQUESTS requests to one host while rotating between two profiles
+async fn rotating_requests(upstream: SocketAddr, preserve_tls_sessions: bool) -> Duration {
+    let config = Config {
+        allowed_profiles: vec!["Chrome131".to_string(), "Firefox133".to_string()],
+        random_seed: Some(7),
+        preserve_tls_sessions,
+        ..Config::default()
+    };
+    let manager = SessionManager::new(SessionConfig::from_config(&config).unwrap());
+    let url = format!("https://{}/", upstream);
+
+    let start = Instant::now();
+    for _ in 0..REQUESTS {
+        let (client, _) = manager.get_or_create_session("127.0.0.1").unwrap();
+        client.get(&url).send().await.unwrap().bytes().await.unwrap();
+    }
+    start.elapsed()
+}
+
+// Timing comparison for preserve_tls_sessions, run with
+// `cargo test --release --test tls_sessions -- --ignored --nocapture`
+#[tokio::test]
+#[ignore]
+async fn preserved_clients_skip_handshakes_on_rotation() {
+    let upstream = start_tls_upstream("resumption-origin", &[b"h2", b"http/1.1"]).await;
+    let rebuilt = rotating_requests(upstream, false).await;
+    let preserved = rotating_requests(upstream, true).await;
+    println!(
+        "{:?} per request when rotation rebuilds the client, {:?} with preserve_tls_sessions",
+        rebuilt / REQUESTS, preserved / REQUESTS
+    );
+    assert!(preserved < rebuilt, "preserved {:?}, rebuilt {:?}", preserved, rebuilt);
+}
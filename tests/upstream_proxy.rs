@@ -0,0 +1,26 @@
+mod common;
+
+use hyper::Response;
+use boring_proxy::{config::Config, types::full};
+use common::{raw_request, start_proxy, start_upstream, test_config};
+
+// Requests are chained through the configured HTTP proxy in absolute form, with its credentials
+#[tokio::test]
+async fn requests_go_through_the_upstream_proxy() {
+    let dummy_proxy = start_upstream(|req| async move {
+        let auth = req.headers().get(hyper::header::PROXY_AUTHORIZATION)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        Response::new(full(format!("{} {}", req.uri(), auth)))
+    }).await;
+    let config = Config {
+        upstream_proxy: Some(format!("http://alice:s3cret@{}", dummy_proxy).parse().unwrap()),
+        ..test_config("upstream-proxy")
+    };
+    let (proxy, _proxy) = start_proxy(config).await;
+
+    let response = raw_request(proxy, "GET http://origin.test/path?q=1 HTTP/1.1\r\nHost: origin.test\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    // alice:s3cret
+    assert!(response.ends_with("http://origin.test/path?q=1 Basic YWxpY2U6czNjcmV0"), "{}", response);
+}
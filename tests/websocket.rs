@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::time::Duration;
+use boring_proxy::websocket_handler::handle_websocket_upgrade;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Role};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::sync::CancellationToken;
+
+const REASON: &str = "session expired";
+
+fn close_4000() -> Message {
+    Message::Close(Some(CloseFrame { code: CloseCode::from(4000), reason: REASON.into() }))
+}
+
+// Upstream WebSocket server that either sends the close frame itself or reports the one it receives
+async fn start_ws_upstream(close_first: bool) -> (String, oneshot::Receiver<Option<CloseFrame<'static>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        if close_first {
+            ws.send(close_4000()).await.unwrap();
+        }
+        while let Some(Ok(msg)) = ws.next().await {
+            if let Message::Close(frame) = msg {
+                let _ = tx.send(frame.map(CloseFrame::into_owned));
+                break;
+            }
+        }
+    });
+    (url, rx)
+}
+
+// Relay between an in-memory client socket and the upstream, returning the client's end
+async fn relay(url: &str) -> WebSocketStream<tokio::io::DuplexStream> {
+    let ws_server = rquest::Client::builder().build().unwrap()
+        .websocket(url)
+        .send().await.unwrap()
+        .into_websocket().await.unwrap();
+
+    let (client_io, proxy_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        handle_websocket_upgrade(proxy_io, ws_server, CancellationToken::new(), Duration::from_secs(1), None, Arc::new(Vec::new()))
+            .await
+            .unwrap();
+    });
+    WebSocketStream::from_raw_socket(client_io, Role::Client, None).await
+}
+
+#[tokio::test]
+async fn client_close_code_and_reason_reach_upstream() {
+    let (url, upstream_close) = start_ws_upstream(false).await;
+    let mut client = relay(&url).await;
+
+    client.send(close_4000()).await.unwrap();
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), upstream_close).await.unwrap().unwrap().unwrap();
+    assert_eq!(u16::from(frame.code), 4000);
+    assert_eq!(frame.reason, REASON);
+}
+
+#[tokio::test]
+async fn upstream_close_code_and_reason_reach_client() {
+    let (url, _) = start_ws_upstream(true).await;
+    let mut client = relay(&url).await;
+
+    let msg = tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap();
+    let Message::Close(Some(frame)) = msg else {
+        panic!("expected a close frame, got {:?}", msg);
+    };
+    assert_eq!(u16::from(frame.code), 4000);
+    assert_eq!(frame.reason, REASON);
+}